@@ -7,19 +7,22 @@ if [ -z "$DAY" ]; then
 fi
 
 FEATURES=""
-INPUT=""
-if [ "$2" == "timeit" ]; then
-  FEATURES="$2"
-  INPUT="${@:3}"
-elif [ "$2" == "print" ]; then
+ARGS=("${@:2}")
+if [ "$2" == "timeit" ] || [ "$2" == "print" ]; then
   FEATURES="$2"
-  INPUT="${@:3}"
-else
-  INPUT="${@:2}"
+  ARGS=("${@:3}")
+fi
+
+# The input file comes first unless it's left out, anything after it goes to the binary,
+# e.g. `./run.sh 18 ../input/day18 --trace` or `./run.sh 18 --trace`.
+INPUT=""
+if [ ${#ARGS[@]} -gt 0 ] && [[ "${ARGS[0]}" != --* ]]; then
+  INPUT="${ARGS[0]}"
+  ARGS=("${ARGS[@]:1}")
 fi
 
 if [ -z "$INPUT" ]; then
   INPUT="../input/day$DAY"
 fi
 
-RUST_BACKTRACE=1 cargo run --features="$FEATURES" --bin "day$DAY" --release "$INPUT"
+RUST_BACKTRACE=1 cargo run --features="$FEATURES" --bin "day$DAY" --release "$INPUT" "${ARGS[@]}"
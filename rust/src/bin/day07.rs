@@ -5,50 +5,126 @@ use std::io::BufReader;
 
 use anyhow::{anyhow, Context, Result};
 
-use utils::measure;
+use utils::{flag, measure};
 
 type Input = Vec<i32>;
 
-fn least_fuel<F>(positions: &Vec<i32>, fuel_cost_per_steps: F) -> i32
+#[derive(Debug, PartialEq)]
+struct Alignment {
+    position: i32,
+    fuel: i64,
+}
+
+/// Total fuel for moving all crabs to `pos`, where `cost` gives the fuel needed for a
+/// single crab to move a given number of steps.
+fn total_fuel<F>(positions: &[i32], pos: i32, cost: F) -> i64
 where
-    F: Fn(i32) -> i32,
+    F: Fn(i64) -> i64,
 {
-    let max_pos = *positions.iter().max().unwrap();
-    let mut least_fuel = 0;
-
-    for pos in 0..max_pos {
-        let mut fuel = 0;
-        for p in positions {
-            let steps = (p - pos).abs();
-            fuel += fuel_cost_per_steps(steps);
-        }
-        if least_fuel == 0 || fuel < least_fuel {
-            least_fuel = fuel;
-        }
+    positions
+        .iter()
+        .map(|&p| cost((p as i64 - pos as i64).abs()))
+        .sum()
+}
+
+fn linear_cost(steps: i64) -> i64 {
+    steps
+}
+
+fn triangular_cost(steps: i64) -> i64 {
+    steps * (steps + 1) / 2
+}
+
+/// Picks the cheapest of the given candidate positions, preferring the lowest position on ties.
+fn best_of<I, F>(positions: &[i32], candidates: I, cost: F) -> Option<Alignment>
+where
+    I: IntoIterator<Item = i32>,
+    F: Fn(i64) -> i64,
+{
+    candidates
+        .into_iter()
+        .map(|position| Alignment {
+            position,
+            fuel: total_fuel(positions, position, &cost),
+        })
+        .min_by_key(|a| (a.fuel, a.position))
+}
+
+/// With linear cost the total fuel is minimized at the median.
+fn align_linear(positions: &[i32]) -> Option<Alignment> {
+    if positions.is_empty() {
+        return None;
     }
+    let mut sorted = positions.to_vec();
+    sorted.sort_unstable();
+    let median = sorted[(sorted.len() - 1) / 2];
+    best_of(positions, [median], linear_cost)
+}
 
-    least_fuel
+/// With triangular cost the optimum is always within half a step of the mean, so it's
+/// enough to check the positions around it.
+fn align_triangular(positions: &[i32]) -> Option<Alignment> {
+    if positions.is_empty() {
+        return None;
+    }
+    let sum = positions.iter().map(|&p| p as i64).sum::<i64>();
+    let mean = sum.div_euclid(positions.len() as i64) as i32;
+    best_of(positions, (mean - 1)..=(mean + 1), triangular_cost)
 }
 
-fn part1(input: &Input) -> i32 {
-    least_fuel(input, |steps| steps)
+/// Ternary search for the best position given any per crab cost that is convex and
+/// non-decreasing in the number of steps, which makes the total fuel convex in position.
+fn align_convex<F>(positions: &[i32], cost: F) -> Option<Alignment>
+where
+    F: Fn(i64) -> i64,
+{
+    let mut lo = *positions.iter().min()?;
+    let mut hi = *positions.iter().max()?;
+
+    while hi - lo > 2 {
+        let third = (hi - lo) / 3;
+        let m1 = lo + third;
+        let m2 = hi - third;
+        if total_fuel(positions, m1, &cost) <= total_fuel(positions, m2, &cost) {
+            hi = m2 - 1;
+        } else {
+            lo = m1 + 1;
+        }
+    }
+
+    best_of(positions, lo..=hi, cost)
 }
 
-fn part2(input: &Input) -> i32 {
-    let max_pos = *input.iter().max().unwrap();
+fn part1(input: &Input) -> i64 {
+    align_linear(input).map_or(0, |a| a.fuel)
+}
 
-    let (fuel_per_steps, _) =
-        (1..(max_pos + 2)).fold((vec![], 0), |(mut fuel_per_step, fuel), pos| {
-            fuel_per_step.push(fuel);
-            (fuel_per_step, fuel + pos)
-        });
+fn part2(input: &Input) -> i64 {
+    align_triangular(input).map_or(0, |a| a.fuel)
+}
 
-    least_fuel(input, |steps| fuel_per_steps[steps as usize])
+/// Best alignment when each crab burns `steps^power` fuel.
+fn align_power(input: &Input, power: u32) -> Result<Option<Alignment>> {
+    let span = match (input.iter().min(), input.iter().max()) {
+        (Some(min), Some(max)) => i64::from(*max) - i64::from(*min),
+        _ => return Ok(None),
+    };
+    span.checked_pow(power)
+        .and_then(|fuel| fuel.checked_mul(input.len() as i64))
+        .with_context(|| format!("Fuel for power {} doesn't fit in an i64", power))?;
+    Ok(align_convex(input, |steps| steps.pow(power)))
 }
 
 fn main() -> Result<()> {
     measure(|| {
         let input = input()?;
+        if let Some(power) = flag("--power") {
+            let power = power.parse()?;
+            match align_power(&input, power)? {
+                Some(a) => println!("Power {}: position {}, fuel {}", power, a.position, a.fuel),
+                None => println!("Power {}: no crabs", power),
+            }
+        }
         println!("Part1: {}", part1(&input));
         println!("Part2: {}", part2(&input));
         Ok(())
@@ -100,4 +176,64 @@ mod tests {
         assert_eq!(part2(&as_input(INPUT)?), 168);
         Ok(())
     }
+
+    #[test]
+    fn test_alignment() -> Result<()> {
+        let input = as_input(INPUT)?;
+        assert_eq!(
+            align_linear(&input),
+            Some(Alignment {
+                position: 2,
+                fuel: 37
+            })
+        );
+        assert_eq!(
+            align_triangular(&input),
+            Some(Alignment {
+                position: 5,
+                fuel: 168
+            })
+        );
+        assert_eq!(align_convex(&input, linear_cost), align_linear(&input));
+        assert_eq!(
+            align_convex(&input, triangular_cost),
+            align_triangular(&input)
+        );
+        assert_eq!(align_linear(&[]), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_alignment_edges() {
+        // The optimum being the max position was never tested before
+        let positions = [0, 10, 10];
+        assert_eq!(align_linear(&positions).map(|a| a.position), Some(10));
+        assert_eq!(
+            align_convex(&positions, linear_cost).map(|a| a.position),
+            Some(10)
+        );
+
+        let positions = [-16, -1, -2, 0, -4, -2, -7, -1, -2, -14];
+        assert_eq!(
+            align_triangular(&positions),
+            Some(Alignment {
+                position: -5,
+                fuel: 168
+            })
+        );
+        assert_eq!(
+            align_convex(&positions, |s| s * s).map(|a| a.position),
+            Some(-5)
+        );
+    }
+
+    #[test]
+    fn test_align_power() -> Result<()> {
+        let input = as_input(INPUT)?;
+        assert_eq!(align_power(&input, 1)?, align_linear(&input));
+        assert_eq!(align_power(&input, 2)?.map(|a| a.position), Some(5));
+        assert_eq!(align_power(&vec![], 2)?, None);
+        assert!(align_power(&vec![0, i32::MAX], 3).is_err());
+        Ok(())
+    }
 }
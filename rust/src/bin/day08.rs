@@ -1,4 +1,4 @@
-use std::collections::{BTreeMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet};
 use std::env;
 use std::fs::File;
 use std::io::prelude::*;
@@ -17,8 +17,14 @@ struct Entry {
     output_values: Vec<String>,
 }
 
-impl Entry {
-    const DIGITS: [&'static str; 10] = [
+/// Describes which segments are lit for each digit of a display, digit `i` being `digits[i]`.
+#[derive(Debug)]
+struct Layout {
+    digits: Vec<BTreeSet<char>>,
+}
+
+impl Layout {
+    const SEVEN_SEGMENT: [&'static str; 10] = [
         "abcefg",  // 0
         "cf",      // 1
         "acdeg",   // 2
@@ -31,6 +37,59 @@ impl Entry {
         "abcdfg",  // 9
     ];
 
+    fn new(digits: &[&str]) -> Result<Layout> {
+        let digits = digits
+            .iter()
+            .map(|d| d.chars().collect::<BTreeSet<_>>())
+            .collect::<Vec<_>>();
+        for (i, d) in digits.iter().enumerate() {
+            if digits[..i].contains(d) {
+                return Err(anyhow!(
+                    "Digit {} has the same segments as another digit",
+                    i
+                ));
+            }
+        }
+        Ok(Layout { digits })
+    }
+
+    fn seven_segment() -> Layout {
+        Layout::new(&Layout::SEVEN_SEGMENT).unwrap()
+    }
+
+    fn segments(&self) -> BTreeSet<char> {
+        self.digits.iter().flatten().copied().collect()
+    }
+
+    fn digit(&self, segments: &BTreeSet<char>) -> Option<usize> {
+        self.digits.iter().position(|d| d == segments)
+    }
+}
+
+/// Maps each signal wire to the display segment it is connected to.
+#[derive(Debug, PartialEq)]
+struct Wiring {
+    wires: BTreeMap<char, char>,
+}
+
+impl Wiring {
+    fn decode(&self, layout: &Layout, pattern: &str) -> Result<usize> {
+        let segments = pattern
+            .chars()
+            .map(|w| {
+                self.wires
+                    .get(&w)
+                    .copied()
+                    .ok_or_else(|| anyhow!("Unknown wire {} in {}", w, pattern))
+            })
+            .collect::<Result<BTreeSet<_>>>()?;
+        layout
+            .digit(&segments)
+            .ok_or_else(|| anyhow!("Pattern {} isn't a digit", pattern))
+    }
+}
+
+impl Entry {
     fn unique_output_values(&self) -> i32 {
         let mut unique = 0;
         for o in &self.output_values {
@@ -42,115 +101,114 @@ impl Entry {
         unique
     }
 
-    fn digits(&self) -> Vec<String> {
-        let seg_1 = self.signal_patterns.iter().find(|s| s.len() == 2).unwrap();
-        let seg_4 = self.signal_patterns.iter().find(|s| s.len() == 4).unwrap();
-        let seg_7 = self.signal_patterns.iter().find(|s| s.len() == 3).unwrap();
-
-        let mut freqs: BTreeMap<char, usize> = BTreeMap::new();
-        for sig in &self.signal_patterns {
-            for ch in sig.chars() {
-                *freqs.entry(ch).or_default() += 1;
+    /// All distinct patterns seen for this entry, output values included.
+    fn observations(&self) -> Vec<BTreeSet<char>> {
+        let mut observed = vec![];
+        for p in self.signal_patterns.iter().chain(&self.output_values) {
+            let p = p.chars().collect::<BTreeSet<_>>();
+            if !observed.contains(&p) {
+                observed.push(p);
             }
         }
+        observed
+    }
 
-        let mut mapping = BTreeMap::new();
-        mapping.insert('e', *freqs.iter().find(|(_, &f)| f == 4).unwrap().0);
-        mapping.insert('b', *freqs.iter().find(|(_, &f)| f == 6).unwrap().0);
-        mapping.insert('f', *freqs.iter().find(|(_, &f)| f == 9).unwrap().0);
-
-        mapping.insert(
-            'a',
-            seg_7
-                .chars()
-                .filter(|&c| !seg_1.contains(c))
-                .next()
-                .unwrap(),
-        );
-        mapping.insert(
-            'c',
-            *freqs
-                .iter()
-                .find(|(&ch, &f)| f == 8 && ch != mapping[&'a'])
-                .unwrap()
-                .0,
-        );
-
-        mapping.insert(
-            'd',
-            *freqs
-                .iter()
-                .find(|(&ch, &f)| f == 7 && seg_4.contains(ch))
-                .unwrap()
-                .0,
-        );
-        mapping.insert(
-            'g',
-            *freqs
-                .iter()
-                .find(|(&ch, &f)| f == 7 && ch != mapping[&'d'])
-                .unwrap()
-                .0,
-        );
+    /// Deduces the wiring by assigning wires to segments one at a time, backtracking as soon as
+    /// some observed pattern can no longer be any digit of the layout.
+    fn wiring(&self, layout: &Layout) -> Result<Wiring> {
+        let segments = layout.segments().into_iter().collect::<Vec<_>>();
+        let observations = self.observations();
 
-        let known_set = &self
-            .signal_patterns
+        if let Some(w) = observations
             .iter()
-            .enumerate()
-            .map(|(i, s)| (s.chars().collect::<HashSet<_>>(), i))
-            .collect::<Vec<_>>();
+            .flatten()
+            .find(|w| !segments.contains(w))
+        {
+            return Err(anyhow!("Unknown wire {}", w));
+        }
 
-        Entry::DIGITS
-            .iter()
-            .map(|&digit| {
-                let set = digit.chars().map(|ch| mapping[&ch]).collect::<HashSet<_>>();
-                known_set
-                    .iter()
-                    .find(|(s, _)| s == &set)
-                    .map(|&(_, i)| &self.signal_patterns[i])
-                    .unwrap()
-                    .to_owned()
+        fn consistent(
+            layout: &Layout,
+            observations: &[BTreeSet<char>],
+            wires: &BTreeMap<char, char>,
+        ) -> bool {
+            observations.iter().all(|obs| {
+                layout.digits.iter().any(|d| {
+                    d.len() == obs.len()
+                        && wires.iter().all(|(w, s)| obs.contains(w) == d.contains(s))
+                })
             })
-            .collect()
-    }
-
-    fn output(&self) -> i32 {
-        let digits = self
-            .digits()
-            .iter()
-            .map(|d| d.chars().collect::<HashSet<_>>())
-            .collect::<Vec<_>>();
+        }
 
-        let mut out = 0;
-        let mut mul = 1;
-        for ov in self.output_values.iter().rev() {
-            let ov = ov.chars().collect::<HashSet<_>>();
+        fn search(
+            layout: &Layout,
+            observations: &[BTreeSet<char>],
+            segments: &[char],
+            wires: &mut BTreeMap<char, char>,
+            solutions: &mut Vec<BTreeMap<char, char>>,
+        ) {
+            if solutions.len() > 1 {
+                return;
+            }
+            let wire = match segments.iter().find(|w| !wires.contains_key(w)) {
+                Some(&w) => w,
+                None => {
+                    solutions.push(wires.clone());
+                    return;
+                }
+            };
+            for &seg in segments {
+                if wires.values().any(|&s| s == seg) {
+                    continue;
+                }
+                wires.insert(wire, seg);
+                if consistent(layout, observations, wires) {
+                    search(layout, observations, segments, wires, solutions);
+                }
+                wires.remove(&wire);
+            }
+        }
 
-            let digit = digits
-                .iter()
-                .enumerate()
-                .find(|&(_, d)| d == &ov)
-                .map(|(i, _)| i)
-                .unwrap();
+        let mut solutions = vec![];
+        search(
+            layout,
+            &observations,
+            &segments,
+            &mut BTreeMap::new(),
+            &mut solutions,
+        );
 
-            out += (digit as i32) * mul;
-            mul *= 10;
+        match solutions.len() {
+            0 => Err(anyhow!("Contradictory patterns, no wiring matches")),
+            1 => Ok(Wiring {
+                wires: solutions.remove(0),
+            }),
+            _ => Err(anyhow!("Ambiguous patterns, more than one wiring matches")),
         }
+    }
+
+    /// The output value read in the base given by the number of digits in the layout.
+    fn output(&self, layout: &Layout) -> Result<i64> {
+        let wiring = self.wiring(layout)?;
+        let base = layout.digits.len() as i64;
 
-        out
+        self.output_values.iter().try_fold(0, |out, ov| {
+            Ok(out * base + wiring.decode(layout, ov)? as i64)
+        })
     }
 }
 
-fn solve(input: &Input) -> (i32, i32) {
-    input.iter().fold((0, 0), |(p1, p2), e| {
-        (p1 + e.unique_output_values(), p2 + e.output())
+fn solve(input: &Input) -> Result<(i32, i64)> {
+    let layout = Layout::seven_segment();
+    input.iter().try_fold((0, 0), |(p1, p2), e| {
+        Ok((p1 + e.unique_output_values(), p2 + e.output(&layout)?))
     })
 }
 
 fn main() -> Result<()> {
     measure(|| {
         let input = input()?;
-        let (part1, part2) = solve(&input);
+        let (part1, part2) = solve(&input)?;
         println!("Part1: {}", part1);
         println!("Part2: {}", part2);
         Ok(())
@@ -227,13 +285,74 @@ mod tests {
 
     #[test]
     fn test_part1() -> Result<()> {
-        assert_eq!(solve(&as_input(INPUT)?).0, 26);
+        assert_eq!(solve(&as_input(INPUT)?)?.0, 26);
         Ok(())
     }
 
     #[test]
     fn test_part2() -> Result<()> {
-        assert_eq!(solve(&as_input(INPUT)?).1, 61229);
+        assert_eq!(solve(&as_input(INPUT)?)?.1, 61229);
+        Ok(())
+    }
+
+    #[test]
+    fn test_wiring() -> Result<()> {
+        let entry =
+            "acedgfb cdfbe gcdfa fbcad dab cefabd cdfgeb eafb cagedb ab | cdfeb fcadb cdfeb cdbaf"
+                .parse::<Entry>()?;
+        let wiring = entry.wiring(&Layout::seven_segment())?;
+        assert_eq!(
+            wiring.wires,
+            "deafgbc".chars().zip("abcdefg".chars()).collect()
+        );
+        assert_eq!(entry.output(&Layout::seven_segment())?, 5353);
+        Ok(())
+    }
+
+    #[test]
+    fn test_wiring_hex() -> Result<()> {
+        let mut hex = Layout::SEVEN_SEGMENT.to_vec();
+        hex.extend(["abcdef", "bdefg", "abeg", "cdefg", "abdeg", "abde"]);
+        let hex = Layout::new(&hex)?;
+
+        // Wire a is connected to segment g, wire b to f and so on
+        let scramble = |d: &str| {
+            d.chars()
+                .map(|c| (b'g' - (c as u8 - b'a')) as char)
+                .collect::<String>()
+        };
+        let patterns = hex
+            .digits
+            .iter()
+            .map(|d| scramble(&d.iter().collect::<String>()));
+        let entry = Entry {
+            signal_patterns: patterns.collect(),
+            output_values: vec![scramble("abdeg"), scramble("abcdef"), scramble("acf")],
+        };
+
+        let wiring = entry.wiring(&hex)?;
+        assert_eq!(
+            wiring.wires,
+            "gfedcba".chars().zip("abcdefg".chars()).collect()
+        );
+        assert_eq!(entry.output(&hex)?, 0xEA7);
+        Ok(())
+    }
+
+    #[test]
+    fn test_wiring_errors() -> Result<()> {
+        let layout = Layout::seven_segment();
+
+        let ambiguous = "ab | ab ab ab ab".parse::<Entry>()?;
+        assert!(ambiguous.wiring(&layout).is_err());
+
+        let contradictory = "ab cd | ab cd".parse::<Entry>()?;
+        assert!(contradictory.wiring(&layout).is_err());
+
+        let unknown = "ab xy | ab ab".parse::<Entry>()?;
+        assert!(unknown.wiring(&layout).is_err());
+
+        assert!(Layout::new(&["ab", "ba"]).is_err());
         Ok(())
     }
 }
@@ -1,4 +1,3 @@
-use std::collections::VecDeque;
 use std::env;
 use std::fs::File;
 use std::io::prelude::*;
@@ -6,7 +5,8 @@ use std::io::BufReader;
 
 use anyhow::{Context, Result};
 
-use utils::measure;
+use utils::union_find::label_components;
+use utils::{flag, measure};
 
 type Input = Heightmap;
 
@@ -54,34 +54,101 @@ impl Heightmap {
             .collect()
     }
 
-    fn basins(&self) -> Vec<usize> {
-        let mut basins = vec![];
+    /// Labels every cell lower than `wall` with the id of the basin it belongs to, a basin being
+    /// all such cells connected to each other without passing a wall.
+    fn basin_map(&self, wall: u8) -> BasinMap {
+        let labels = label_components(self.width(), self.height(), |x, y| self.at(x, y) < wall);
 
-        for (x, y) in self.low_points() {
-            let mut basin = vec![];
+        let mut cells: Vec<Vec<(usize, usize)>> = vec![];
+        for (y, row) in labels.iter().enumerate() {
+            for (x, label) in row.iter().enumerate() {
+                if let &Some(id) = label {
+                    if id == cells.len() {
+                        cells.push(vec![]);
+                    }
+                    cells[id].push((x, y));
+                }
+            }
+        }
 
-            let mut queue = VecDeque::new();
-            queue.push_back((x, y));
+        let basins = cells
+            .iter()
+            .enumerate()
+            .map(|(id, cells)| self.basin(id, cells, &labels))
+            .collect();
 
-            while let Some((x, y)) = queue.pop_front() {
-                if basin.contains(&(x, y)) {
-                    continue;
-                }
-                if self.at(x, y) == 9 {
-                    continue;
-                }
+        BasinMap { labels, basins }
+    }
 
-                basin.push((x, y));
+    fn basin(&self, id: usize, cells: &[(usize, usize)], labels: &[Vec<Option<usize>>]) -> Basin {
+        let low_point = *cells
+            .iter()
+            .min_by_key(|&&(x, y)| (self.at(x, y), y, x))
+            .unwrap();
+        let max_height = cells.iter().map(|&(x, y)| self.at(x, y)).max().unwrap();
 
-                for pos in self.adjacent(x, y) {
-                    queue.push_back(pos);
-                }
-            }
+        let mut boundary = cells
+            .iter()
+            .filter(|&&(x, y)| {
+                let adjacent = self.adjacent(x, y);
+                adjacent.len() < 4 || adjacent.iter().any(|&(x, y)| labels[y][x] != Some(id))
+            })
+            .copied()
+            .collect::<Vec<_>>();
+        boundary.sort_by_key(|&(x, y)| (y, x));
 
-            basins.push(basin);
+        Basin {
+            id,
+            low_point,
+            size: cells.len(),
+            boundary,
+            max_height,
         }
+    }
+}
+
+#[derive(Debug)]
+struct Basin {
+    id: usize,
+    /// The lowest cell, picking the first in reading order if there are several.
+    low_point: (usize, usize),
+    size: usize,
+    /// Cells of the basin next to a wall or the edge of the map.
+    boundary: Vec<(usize, usize)>,
+    max_height: u8,
+}
+
+#[derive(Debug)]
+struct BasinMap {
+    labels: Vec<Vec<Option<usize>>>,
+    basins: Vec<Basin>,
+}
+
+impl BasinMap {
+    const COLORS: [u8; 6] = [31, 32, 33, 34, 35, 36];
+
+    fn label(&self, x: usize, y: usize) -> Option<usize> {
+        self.labels[y][x]
+    }
 
-        basins.iter().map(|b| b.len()).collect()
+    /// Renders the heights with each basin in its own colour using ANSI escape codes, walls are
+    /// left uncoloured.
+    fn render(&self, heightmap: &Heightmap) -> String {
+        let mut out = String::new();
+        for y in 0..heightmap.height() {
+            for x in 0..heightmap.width() {
+                let h = heightmap.at(x, y);
+                match self.label(x, y) {
+                    Some(id) => {
+                        let color = BasinMap::COLORS[id % BasinMap::COLORS.len()];
+                        out.push_str(&format!("\x1b[{}m{}\x1b[0m", color, h));
+                    }
+                    None => out.push_str(&h.to_string()),
+                }
+            }
+            out.push('\n');
+        }
+        out
     }
 }
 
@@ -94,17 +161,41 @@ fn part1(input: &Input) -> u32 {
 }
 
 fn part2(input: &Input) -> usize {
-    let mut basins = input.basins();
-    basins.sort();
+    let basin_map = input.basin_map(9);
+
+    #[cfg(feature = "print")]
+    print!("{}", basin_map.render(input));
+
+    let mut basins = basin_map.basins.iter().map(|b| b.size).collect::<Vec<_>>();
+    basins.sort_unstable();
 
     basins.iter().rev().take(3).product()
 }
 
+/// Prints the basins left by walls of `wall_height` and up, with the map they're on.
+fn print_basins(input: &Input, wall_height: u8) {
+    let basin_map = input.basin_map(wall_height);
+    print!("{}", basin_map.render(input));
+    for basin in &basin_map.basins {
+        println!(
+            "Basin {}: low point {:?}, size {}, {} boundary cells, max height {}",
+            basin.id,
+            basin.low_point,
+            basin.size,
+            basin.boundary.len(),
+            basin.max_height
+        );
+    }
+}
+
 fn main() -> Result<()> {
     measure(|| {
         let input = input()?;
         println!("Part1: {}", part1(&input));
         println!("Part2: {}", part2(&input));
+        if let Some(wall_height) = flag("--basins") {
+            print_basins(&input, wall_height.parse()?);
+        }
         Ok(())
     })
 }
@@ -158,4 +249,43 @@ mod tests {
         assert_eq!(part2(&as_input(INPUT)?), 1134);
         Ok(())
     }
+
+    #[test]
+    fn test_basin_map() -> Result<()> {
+        let input = as_input(INPUT)?;
+        let basin_map = input.basin_map(9);
+
+        assert_eq!(basin_map.basins.len(), 4);
+        assert_eq!(basin_map.label(0, 0), Some(0));
+        assert_eq!(basin_map.label(2, 0), None);
+        assert_eq!(basin_map.label(9, 0), Some(1));
+
+        let basin = &basin_map.basins[1];
+        assert_eq!(basin.id, 1);
+        assert_eq!(basin.low_point, (9, 0));
+        assert_eq!(basin.size, 9);
+        assert_eq!(basin.max_height, 4);
+        assert_eq!(basin.boundary.len(), 9);
+
+        let basin = &basin_map.basins[2];
+        assert_eq!(basin.low_point, (2, 2));
+        assert_eq!(basin.size, 14);
+        assert!(!basin.boundary.contains(&(2, 2)));
+
+        let sizes = input
+            .basin_map(5)
+            .basins
+            .iter()
+            .map(|b| b.size)
+            .collect::<Vec<_>>();
+        assert_eq!(sizes, vec![3, 9]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_render() -> Result<()> {
+        let input = as_input("\n19\n99")?;
+        assert_eq!(input.basin_map(9).render(&input), "\x1b[31m1\x1b[0m9\n99\n");
+        Ok(())
+    }
 }
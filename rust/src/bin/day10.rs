@@ -1,80 +1,214 @@
-use std::collections::HashMap;
+use std::collections::HashSet;
 use std::env;
+use std::fmt;
 use std::fs::File;
 use std::io::prelude::*;
 use std::io::BufReader;
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 
 use utils::measure;
 
 type Input = Vec<String>;
 
-fn solve(input: &Input) -> (i32, i64) {
-    let delimiters = [('(', ')'), ('[', ']'), ('{', '}'), ('<', '>')]
-        .into_iter()
-        .flat_map(|(o, c)| [(o, (Some(o), Some(c))), (c, (Some(o), Some(c)))].into_iter())
-        .collect::<HashMap<_, _>>();
+#[derive(Debug, Clone, Copy)]
+struct Delimiter {
+    open: char,
+    close: char,
+    illegal_score: i64,
+    completion_score: i64,
+}
 
-    let scores_illegal_char = [(')', 3_i32), (']', 57), ('}', 1197), ('>', 25137)]
-        .into_iter()
-        .collect::<HashMap<_, _>>();
+#[derive(Debug, PartialEq)]
+enum LintError {
+    /// A closing delimiter that doesn't close the innermost open chunk, `expected` being `None`
+    /// when there was no open chunk at all.
+    UnexpectedCloser {
+        column: usize,
+        found: char,
+        expected: Option<char>,
+    },
+    UnknownCharacter {
+        column: usize,
+        found: char,
+    },
+}
 
-    let scores_completion_char = [(')', 1_i64), (']', 2), ('}', 3), ('>', 4)]
-        .into_iter()
-        .collect::<HashMap<_, _>>();
+impl fmt::Display for LintError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LintError::UnexpectedCloser {
+                column,
+                found,
+                expected: Some(expected),
+            } => write!(f, "{}: expected '{}', found '{}'", column, expected, found),
+            LintError::UnexpectedCloser {
+                column,
+                found,
+                expected: None,
+            } => write!(f, "{}: unmatched '{}'", column, found),
+            LintError::UnknownCharacter { column, found } => {
+                write!(f, "{}: unknown character '{}'", column, found)
+            }
+        }
+    }
+}
 
-    let mut illegal = vec![];
-    let mut completion_scores = vec![];
+#[derive(Debug)]
+struct Lint {
+    errors: Vec<LintError>,
+    /// Closers needed to complete the chunks still open at the end of the line.
+    completion: String,
+}
 
-    'outer: for i in input {
-        let mut chunks = vec![];
-        for c in i.chars() {
-            let delim = delimiters[&c];
+#[derive(Debug)]
+struct Linter {
+    delimiters: Vec<Delimiter>,
+}
 
-            match delim {
-                (Some(oc), _) if oc == c => {
-                    chunks.push(c);
-                }
-                (Some(oc), Some(cc)) if cc == c => {
-                    let popped = chunks.pop();
-                    if popped != Some(oc) {
-                        illegal.push(c);
-                        continue 'outer;
+impl Linter {
+    fn new(delimiters: &[Delimiter]) -> Result<Linter> {
+        let mut seen = HashSet::new();
+        for d in delimiters {
+            if !seen.insert(d.open) || !seen.insert(d.close) {
+                return Err(anyhow!("Delimiter {}{} is ambiguous", d.open, d.close));
+            }
+        }
+        Ok(Linter {
+            delimiters: delimiters.to_vec(),
+        })
+    }
+
+    fn navigation_subsystem() -> Linter {
+        let delimiters = [
+            ('(', ')', 3, 1),
+            ('[', ']', 57, 2),
+            ('{', '}', 1197, 3),
+            ('<', '>', 25137, 4),
+        ]
+        .iter()
+        .map(
+            |&(open, close, illegal_score, completion_score)| Delimiter {
+                open,
+                close,
+                illegal_score,
+                completion_score,
+            },
+        )
+        .collect::<Vec<_>>();
+        Linter::new(&delimiters).unwrap()
+    }
+
+    fn opened_by(&self, c: char) -> Option<&Delimiter> {
+        self.delimiters.iter().find(|d| d.open == c)
+    }
+
+    fn closed_by(&self, c: char) -> Option<&Delimiter> {
+        self.delimiters.iter().find(|d| d.close == c)
+    }
+
+    /// Checks the whole line, recovering from errors by skipping the offending character so that
+    /// any later errors are found as well.
+    fn lint(&self, line: &str) -> Lint {
+        let mut errors = vec![];
+        let mut chunks: Vec<&Delimiter> = vec![];
+
+        for (i, c) in line.chars().enumerate() {
+            let column = i + 1;
+            if let Some(d) = self.opened_by(c) {
+                chunks.push(d);
+            } else if self.closed_by(c).is_some() {
+                match chunks.last() {
+                    Some(open) if open.close == c => {
+                        chunks.pop();
+                    }
+                    expected => {
+                        errors.push(LintError::UnexpectedCloser {
+                            column,
+                            found: c,
+                            expected: expected.map(|e| e.close),
+                        });
                     }
                 }
-                _ => unreachable!(),
+            } else {
+                errors.push(LintError::UnknownCharacter { column, found: c });
             }
         }
 
-        if !chunks.is_empty() {
-            let mut completed_by = String::new();
-            for c in chunks.iter().rev() {
-                if let (_, Some(cc)) = delimiters[&c] {
-                    completed_by.push(cc);
-                }
+        Lint {
+            errors,
+            completion: chunks.iter().rev().map(|d| d.close).collect(),
+        }
+    }
+
+    /// Syntax error score of a corrupted line, from its first unexpected closer.
+    fn illegal_score(&self, lint: &Lint) -> Option<i64> {
+        lint.errors.iter().find_map(|e| match e {
+            LintError::UnexpectedCloser { found, .. } => {
+                self.closed_by(*found).map(|d| d.illegal_score)
             }
+            _ => None,
+        })
+    }
+
+    /// Completion score of an incomplete line, lines with errors aren't scored.
+    fn completion_score(&self, lint: &Lint) -> Option<i64> {
+        if !lint.errors.is_empty() || lint.completion.is_empty() {
+            return None;
+        }
+        lint.completion.chars().try_fold(0, |score, c| {
+            self.closed_by(c).map(|d| score * 5 + d.completion_score)
+        })
+    }
 
-            let mut completion_score = 0_i64;
-            for c in completed_by.chars() {
-                let score = scores_completion_char[&c];
-                completion_score = completion_score * 5 + score;
+    /// Human readable diagnostics for all lines as `line:column: message`.
+    fn diagnostics(&self, input: &[String]) -> Vec<String> {
+        let mut diagnostics = vec![];
+        for (i, line) in input.iter().enumerate() {
+            let lint = self.lint(line);
+            for e in &lint.errors {
+                diagnostics.push(format!("{}:{}", i + 1, e));
+            }
+            if lint.errors.is_empty() && !lint.completion.is_empty() {
+                diagnostics.push(format!(
+                    "{}:{}: incomplete, complete with '{}'",
+                    i + 1,
+                    line.chars().count() + 1,
+                    lint.completion
+                ));
             }
-            completion_scores.push(completion_score);
         }
+        diagnostics
     }
+}
+
+fn solve(input: &Input) -> (i64, i64) {
+    let linter = Linter::navigation_subsystem();
+    let lints = input.iter().map(|l| linter.lint(l)).collect::<Vec<_>>();
 
-    completion_scores.sort();
+    let mut completion_scores = lints
+        .iter()
+        .filter_map(|l| linter.completion_score(l))
+        .collect::<Vec<_>>();
+    completion_scores.sort_unstable();
 
     (
-        illegal.iter().map(|c| scores_illegal_char[c]).sum(),
-        completion_scores[completion_scores.len() / 2],
+        lints.iter().filter_map(|l| linter.illegal_score(l)).sum(),
+        completion_scores
+            .get(completion_scores.len() / 2)
+            .copied()
+            .unwrap_or(0),
     )
 }
 
 fn main() -> Result<()> {
     measure(|| {
         let input = input()?;
+        if cfg!(feature = "print") || env::args().any(|arg| arg == "--diagnostics") {
+            for d in Linter::navigation_subsystem().diagnostics(&input) {
+                println!("{}", d);
+            }
+        }
         let (part1, part2) = solve(&input);
         println!("Part1: {}", part1);
         println!("Part2: {}", part2);
@@ -132,4 +266,72 @@ mod tests {
         assert_eq!(solve(&as_input(INPUT)?).1, 288957);
         Ok(())
     }
+
+    #[test]
+    fn test_lint() {
+        let linter = Linter::navigation_subsystem();
+
+        let lint = linter.lint("{([(<{}[<>[]}>{[]{[(<()>");
+        assert_eq!(
+            lint.errors,
+            vec![
+                LintError::UnexpectedCloser {
+                    column: 13,
+                    found: '}',
+                    expected: Some(']'),
+                },
+                LintError::UnexpectedCloser {
+                    column: 14,
+                    found: '>',
+                    expected: Some(']'),
+                },
+            ]
+        );
+        assert_eq!(linter.illegal_score(&lint), Some(1197));
+        assert_eq!(linter.completion_score(&lint), None);
+
+        let lint = linter.lint("<{([{{}}[<[[[<>{}]]]>[]]");
+        assert!(lint.errors.is_empty());
+        assert_eq!(lint.completion, "])}>");
+        assert_eq!(linter.completion_score(&lint), Some(294));
+
+        let lint = linter.lint("()x)");
+        assert_eq!(
+            lint.errors,
+            vec![
+                LintError::UnknownCharacter {
+                    column: 3,
+                    found: 'x'
+                },
+                LintError::UnexpectedCloser {
+                    column: 4,
+                    found: ')',
+                    expected: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_custom_delimiters() -> Result<()> {
+        let delimiter = |open, close| Delimiter {
+            open,
+            close,
+            illegal_score: 1,
+            completion_score: 1,
+        };
+        let linter = Linter::new(&[delimiter('/', '\\'), delimiter('«', '»')])?;
+        assert_eq!(linter.lint("«/\\/").completion, "\\»");
+        assert!(Linter::new(&[delimiter('(', ')'), delimiter(')', '(')]).is_err());
+
+        let diagnostics = linter.diagnostics(&["«/»".to_owned(), "/«".to_owned()]);
+        assert_eq!(
+            diagnostics,
+            vec![
+                "1:3: expected '\\', found '»'",
+                "2:3: incomplete, complete with '»\\'",
+            ]
+        );
+        Ok(())
+    }
 }
@@ -1,28 +1,36 @@
+use std::cmp::max;
+use std::collections::{HashMap, VecDeque};
 use std::env;
 use std::fs::File;
 use std::io::prelude::*;
 use std::io::BufReader;
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 
-use utils::measure;
+use utils::{flag, measure};
 
 type Input = OctoGrid;
 
-const W: usize = 10;
-const H: usize = 10;
-
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 struct OctoGrid {
-    energy_levels: [[u8; W]; H],
+    width: usize,
+    height: usize,
+    energy_levels: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq)]
+struct Cycle {
+    /// Number of steps before the grid first enters the cycle.
+    start: usize,
+    length: usize,
 }
 
 impl OctoGrid {
     fn adjacent(&self, x: usize, y: usize) -> Vec<(usize, usize)> {
         let x = x as i32;
         let y = y as i32;
-        let w = W as i32;
-        let h = H as i32;
+        let w = self.width as i32;
+        let h = self.height as i32;
         [
             (1_i32, 0_i32),
             (1, 1),
@@ -40,71 +48,90 @@ impl OctoGrid {
         .collect()
     }
 
+    /// Bumps the energy level of an octopus, returns true if it just became ready to flash.
+    fn charge(&mut self, x: usize, y: usize) -> bool {
+        let energy = &mut self.energy_levels[y * self.width + x];
+        *energy += 1;
+        *energy == 10
+    }
+
     fn step(&mut self) -> u64 {
-        for y in 0..H {
-            for x in 0..W {
-                self.energy_levels[y][x] += 1;
+        let mut queue = VecDeque::new();
+        for y in 0..self.height {
+            for x in 0..self.width {
+                if self.charge(x, y) {
+                    queue.push_back((x, y));
+                }
             }
         }
 
-        let mut flashed = [[false; W]; H];
-        let mut any_flash = true;
         let mut flash_count = 0;
-
-        while any_flash {
-            any_flash = false;
-            for y in 0..H {
-                for x in 0..W {
-                    if !flashed[y][x] && self.energy_levels[y][x] > 9 {
-                        flashed[y][x] = true;
-                        for (x, y) in self.adjacent(x, y) {
-                            self.energy_levels[y][x] += 1;
-                        }
-                        any_flash = true;
-                        flash_count += 1;
-                    }
+        while let Some((x, y)) = queue.pop_front() {
+            flash_count += 1;
+            for (x, y) in self.adjacent(x, y) {
+                if self.charge(x, y) {
+                    queue.push_back((x, y));
                 }
             }
         }
 
-        for y in 0..H {
-            for x in 0..W {
-                if self.energy_levels[y][x] > 9 {
-                    self.energy_levels[y][x] = 0;
-                }
+        for energy in self.energy_levels.iter_mut() {
+            if *energy > 9 {
+                *energy = 0;
             }
         }
         flash_count
     }
 
     fn is_synchronized(&self) -> bool {
-        for y in 0..H {
-            for x in 0..W {
-                if self.energy_levels[y][x] != 0 {
-                    return false;
-                }
+        self.energy_levels.iter().all(|&e| e == 0)
+    }
+
+    /// Steps a copy of the grid until it's back in a state seen before, giving up after
+    /// `max_steps`.
+    fn find_cycle(&self, max_steps: usize) -> Option<Cycle> {
+        let mut grid = self.clone();
+        let mut seen = HashMap::new();
+
+        for step in 0..=max_steps {
+            if let Some(start) = seen.insert(grid.energy_levels.clone(), step) {
+                return Some(Cycle {
+                    start,
+                    length: step - start,
+                });
             }
+            grid.step();
         }
-        true
+
+        None
     }
 }
 
-fn solve(input: &Input) -> (u64, usize) {
+/// Total flashes after 100 steps, and the first step where every octopus flashes if there is
+/// one. The grid has finitely many states so it always ends up in a cycle, and it can only
+/// synchronize before it has gone around that cycle once.
+fn solve(input: &Input) -> (u64, Option<usize>) {
     let mut energy_levels = input.clone();
+    let cycle = input.find_cycle(usize::MAX);
+    let last_step = cycle.map_or(usize::MAX, |Cycle { start, length }| start + length);
 
     let mut p1_total_flashes = 0;
-    for step in 1.. {
+    let mut synchronized = None;
+    for step in 1..=max(100, last_step) {
         let flashes = energy_levels.step();
         if step <= 100 {
             p1_total_flashes += flashes;
         }
 
-        if energy_levels.is_synchronized() {
-            return (p1_total_flashes, step);
+        if synchronized.is_none() && energy_levels.is_synchronized() {
+            synchronized = Some(step);
+        }
+        if step >= 100 && (synchronized.is_some() || step >= last_step) {
+            break;
         }
     }
 
-    (0, 0)
+    (p1_total_flashes, synchronized)
 }
 
 fn main() -> Result<()> {
@@ -112,23 +139,48 @@ fn main() -> Result<()> {
         let input = input()?;
         let (part1, part2) = solve(&input);
         println!("Part1: {}", part1);
-        println!("Part2: {}", part2);
+        println!(
+            "Part2: {}",
+            part2.context("The octopuses never flash at the same time")?
+        );
+        if let Some(max_steps) = flag("--cycle") {
+            match input.find_cycle(max_steps.parse()?) {
+                Some(Cycle { start, length }) => {
+                    println!("Cycle: from step {}, {} steps long", start, length)
+                }
+                None => println!("Cycle: none within {} steps", max_steps),
+            }
+        }
         Ok(())
     })
 }
 
 fn read_input<R: Read>(reader: BufReader<R>) -> Result<Input> {
-    let mut octo_grid = OctoGrid {
-        ..Default::default()
-    };
+    let mut width = 0;
+    let mut height = 0;
+    let mut energy_levels = vec![];
 
-    for (y, line) in reader.lines().enumerate() {
-        for (x, c) in line?.chars().enumerate() {
-            octo_grid.energy_levels[y][x] = c as u8 - b'0';
+    for line in reader.lines() {
+        let line = line?;
+        if height == 0 {
+            width = line.len();
+        } else if line.len() != width {
+            return Err(anyhow!("Expected row {} to be {} wide", height, width));
+        }
+        for c in line.chars() {
+            let energy = c
+                .to_digit(10)
+                .ok_or_else(|| anyhow!("Invalid energy level {}", c))?;
+            energy_levels.push(energy as u8);
         }
+        height += 1;
     }
 
-    Ok(octo_grid)
+    Ok(OctoGrid {
+        width,
+        height,
+        energy_levels,
+    })
 }
 
 fn input() -> Result<Input> {
@@ -174,7 +226,47 @@ mod tests {
 
     #[test]
     fn test_part2() -> Result<()> {
-        assert_eq!(solve(&as_input(INPUT)?).1, 195);
+        assert_eq!(solve(&as_input(INPUT)?).1, Some(195));
+        Ok(())
+    }
+
+    #[test]
+    fn test_small_grid() -> Result<()> {
+        let mut grid = as_input(
+            "
+            11111
+            19991
+            19191
+            19991
+            11111",
+        )?;
+        assert_eq!((grid.width, grid.height), (5, 5));
+        assert_eq!(grid.step(), 9);
+        assert_eq!(grid, as_input("\n34543\n40004\n50005\n40004\n34543")?);
+        assert!(as_input("\n123\n12").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_cycle() -> Result<()> {
+        let input = as_input(INPUT)?;
+        assert_eq!(
+            input.find_cycle(1000),
+            Some(Cycle {
+                start: 195,
+                length: 10
+            })
+        );
+        assert_eq!(input.find_cycle(100), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_never_synchronized() -> Result<()> {
+        for grid in ["\n02", "\n13", "\n19", "\n1234", "\n0990"] {
+            assert_eq!(solve(&as_input(grid)?).1, None);
+        }
+        assert_eq!(solve(&as_input("\n9")?), (10, Some(1)));
         Ok(())
     }
 }
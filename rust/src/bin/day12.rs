@@ -1,101 +1,345 @@
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::HashMap;
 use std::env;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::prelude::*;
-use std::io::BufReader;
-use std::num::ParseIntError;
+use std::io::{self, BufReader};
 use std::str::FromStr;
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 
-use utils::measure;
+use utils::{flag, measure};
 
-type Input = Vec<Connection>;
+type Input = CaveSystem;
 
 #[derive(Debug)]
 struct Connection {
     caves: [String; 2],
 }
 
-fn count_paths(input: &Input, permit_double_visit: bool) -> usize {
-    const START: u8 = 0;
-    const END: u8 = 1;
-    let mut name_to_id: HashMap<&str, u8> = [("start", START), ("end", END)].into_iter().collect();
-    let mut connections = (0..256).map(|_| HashSet::<u8>::new()).collect::<Vec<_>>();
-    let mut big_caves = [false; 256];
+/// Which small caves a path may revisit: a single small cave may be visited up to `max_visits`
+/// times while all other small caves are visited at most once.
+#[derive(Debug, Clone, Copy)]
+struct VisitRule {
+    max_visits: usize,
+}
 
-    for Connection { caves } in input {
-        for cave in caves {
-            let id = if let Some(&id) = name_to_id.get(cave.as_str()) {
-                id
-            } else {
-                let id = name_to_id.len() as u8;
-                name_to_id.insert(cave, id);
-                id
-            };
-            if cave.chars().all(|c| c.is_uppercase()) {
-                big_caves[id as usize] = true;
+/// Current cave, visited small caves and repeated small cave with its visit count.
+type PathState = (usize, Vec<u64>, Option<(usize, usize)>);
+
+#[derive(Debug)]
+struct CaveSystem {
+    names: Vec<String>,
+    big: Vec<bool>,
+    connections: Vec<Vec<usize>>,
+    start: usize,
+    end: usize,
+}
+
+impl CaveSystem {
+    fn new(connections: &[Connection]) -> Result<CaveSystem> {
+        let mut name_to_id: HashMap<&str, usize> = HashMap::new();
+        let mut cave_system = CaveSystem {
+            names: vec![],
+            big: vec![],
+            connections: vec![],
+            start: 0,
+            end: 0,
+        };
+
+        for Connection { caves } in connections {
+            let mut ids = [0; 2];
+            for (cave, id) in caves.iter().zip(ids.iter_mut()) {
+                *id = *name_to_id.entry(cave).or_insert_with(|| {
+                    cave_system.names.push(cave.to_owned());
+                    cave_system.big.push(cave.chars().all(|c| c.is_uppercase()));
+                    cave_system.connections.push(vec![]);
+                    cave_system.names.len() - 1
+                });
+            }
+
+            let [a, b] = ids;
+            if cave_system.big[a] && cave_system.big[b] {
+                return Err(anyhow!(
+                    "Big caves {} and {} are connected, there would be infinitely many paths",
+                    caves[0],
+                    caves[1]
+                ));
             }
+            cave_system.connections[a].push(b);
+            cave_system.connections[b].push(a);
         }
-        for (a, b) in [(0, 1), (1, 0)] {
-            let id = name_to_id[&caves[a].as_str()];
-            let target = name_to_id[&caves[b].as_str()];
-            connections[id as usize].insert(target);
+
+        cave_system.start = *name_to_id.get("start").context("No start cave")?;
+        cave_system.end = *name_to_id.get("end").context("No end cave")?;
+
+        Ok(cave_system)
+    }
+
+    /// Counts the paths from start to end with a memoized depth first search, without keeping
+    /// the paths themselves around.
+    fn count_paths(&self, rule: VisitRule) -> usize {
+        let mut visited = vec![0_u64; self.names.len().div_ceil(64)];
+        visited[self.start / 64] |= 1 << (self.start % 64);
+
+        self.count_paths_from(self.start, &mut visited, None, rule, &mut HashMap::new())
+    }
+
+    /// `visited` is a bitset of the small caves on the current path and `repeated` the small
+    /// cave visited more than once along with its number of visits, if any.
+    fn count_paths_from(
+        &self,
+        cave: usize,
+        visited: &mut Vec<u64>,
+        repeated: Option<(usize, usize)>,
+        rule: VisitRule,
+        memo: &mut HashMap<PathState, usize>,
+    ) -> usize {
+        let key = (cave, visited.clone(), repeated);
+        if let Some(&count) = memo.get(&key) {
+            return count;
         }
+
+        let mut count = 0;
+        for &next in &self.connections[cave] {
+            if next == self.start {
+                continue;
+            }
+            if next == self.end {
+                count += 1;
+                continue;
+            }
+            if self.big[next] {
+                count += self.count_paths_from(next, visited, repeated, rule, memo);
+                continue;
+            }
+
+            let (word, bit) = (next / 64, 1 << (next % 64));
+            if visited[word] & bit == 0 {
+                visited[word] |= bit;
+                count += self.count_paths_from(next, visited, repeated, rule, memo);
+                visited[word] &= !bit;
+            } else {
+                let repeated = match repeated {
+                    None if rule.max_visits > 1 => Some((next, 2)),
+                    Some((c, n)) if c == next && n < rule.max_visits => Some((c, n + 1)),
+                    _ => continue,
+                };
+                count += self.count_paths_from(next, visited, repeated, rule, memo);
+            }
+        }
+
+        memo.insert(key, count);
+        count
     }
 
-    let mut paths: HashSet<Vec<u8>> = HashSet::new();
+    /// Lazily enumerates the paths from start to end as lists of cave names.
+    fn paths(&self, rule: VisitRule) -> Paths<'_> {
+        let mut visits = vec![0; self.names.len()];
+        visits[self.start] = 1;
 
-    let mut queue: VecDeque<(Vec<u8>, bool)> = VecDeque::new();
-    for &cave in &connections[START as usize] {
-        queue.push_back((vec![START, cave], false));
+        Paths {
+            caves: self,
+            rule,
+            stack: vec![(self.start, 0)],
+            visits,
+            repeated: None,
+        }
     }
 
-    while let Some((caves, has_double_visit)) = queue.pop_front() {
-        if let Some(&last_cave) = caves.last() {
-            for &next_cave in &connections[last_cave as usize] {
-                if next_cave == START {
-                    continue;
-                }
+    /// Writes each path on its own line with the caves separated by commas.
+    fn export_paths<W: Write>(&self, rule: VisitRule, mut writer: W) -> io::Result<()> {
+        for path in self.paths(rule) {
+            writeln!(writer, "{}", path.join(","))?;
+        }
+        Ok(())
+    }
+}
 
-                if next_cave == END {
-                    let mut caves = caves.clone();
-                    caves.push(next_cave);
-                    paths.insert(caves);
-                    continue;
-                }
+/// What to draw on top of the cave graph when exporting it as DOT.
+#[derive(Debug, Clone, Copy)]
+enum Overlay<'a> {
+    None,
+    /// Highlights the connections used by a path given as cave names.
+    Path(&'a [&'a str]),
+    /// Labels each connection with the number of paths using it.
+    Multiplicity(VisitRule),
+}
 
-                let is_big = big_caves[next_cave as usize];
-
-                if is_big
-                    || (permit_double_visit && !has_double_visit)
-                    || !caves.contains(&next_cave)
-                {
-                    let mut next_caves = caves.clone();
-                    next_caves.push(next_cave);
-                    queue.push_front((
-                        next_caves,
-                        has_double_visit || (!is_big && caves.contains(&next_cave)),
-                    ));
+impl CaveSystem {
+    /// Renders the caves as an undirected Graphviz graph, big caves as boxes and the start and
+    /// end caves filled in. Fails if a path to highlight isn't one through the caves.
+    fn to_dot(&self, overlay: Overlay) -> Result<String> {
+        let mut edge_counts: HashMap<(usize, usize), usize> = HashMap::new();
+        match overlay {
+            Overlay::None => {}
+            Overlay::Path(path) => {
+                let ids = path
+                    .iter()
+                    .map(|name| {
+                        self.names
+                            .iter()
+                            .position(|n| n == name)
+                            .ok_or_else(|| anyhow!("Unknown cave {}", name))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                for w in ids.windows(2) {
+                    if !self.connections[w[0]].contains(&w[1]) {
+                        return Err(anyhow!(
+                            "No connection between {} and {}",
+                            self.names[w[0]],
+                            self.names[w[1]]
+                        ));
+                    }
+                    *edge_counts.entry(edge(w[0], w[1])).or_default() += 1;
                 }
             }
+            Overlay::Multiplicity(rule) => {
+                let ids = self
+                    .names
+                    .iter()
+                    .enumerate()
+                    .map(|(id, name)| (name.as_str(), id))
+                    .collect::<HashMap<_, _>>();
+                for path in self.paths(rule) {
+                    for w in path.windows(2) {
+                        *edge_counts.entry(edge(ids[w[0]], ids[w[1]])).or_default() += 1;
+                    }
+                }
+            }
+        }
+
+        let mut dot = String::from("graph caves {\n");
+        for (id, name) in self.names.iter().enumerate() {
+            let mut attrs = vec![if self.big[id] {
+                "shape=box"
+            } else {
+                "shape=ellipse"
+            }];
+            if id == self.start {
+                attrs.push("style=filled, fillcolor=palegreen");
+            } else if id == self.end {
+                attrs.push("style=filled, fillcolor=salmon");
+            }
+            dot.push_str(&format!("    \"{}\" [{}];\n", name, attrs.join(", ")));
+        }
+
+        for (a, connections) in self.connections.iter().enumerate() {
+            for &b in connections.iter().filter(|&&b| a < b) {
+                let attrs = match (overlay, edge_counts.get(&edge(a, b))) {
+                    (Overlay::Path(_), Some(_)) => " [color=blue, penwidth=2]".to_owned(),
+                    (Overlay::Multiplicity(_), count) => {
+                        let count = count.copied().unwrap_or(0);
+                        format!(" [label=\"{}\", weight={}]", count, count)
+                    }
+                    _ => String::new(),
+                };
+                dot.push_str(&format!(
+                    "    \"{}\" -- \"{}\"{};\n",
+                    self.names[a], self.names[b], attrs
+                ));
+            }
+        }
+        dot.push_str("}\n");
+        Ok(dot)
+    }
+}
+
+fn edge(a: usize, b: usize) -> (usize, usize) {
+    (a.min(b), a.max(b))
+}
+
+struct Paths<'a> {
+    caves: &'a CaveSystem,
+    rule: VisitRule,
+    /// The caves of the current path, each with the index of the next connection to try.
+    stack: Vec<(usize, usize)>,
+    visits: Vec<usize>,
+    repeated: Option<usize>,
+}
+
+impl<'a> Paths<'a> {
+    fn can_visit(&self, cave: usize) -> bool {
+        if cave == self.caves.start {
+            false
+        } else if self.caves.big[cave] || self.visits[cave] == 0 {
+            true
+        } else {
+            self.repeated.unwrap_or(cave) == cave && self.visits[cave] < self.rule.max_visits
         }
     }
 
-    paths.len()
+    fn path(&self) -> Vec<&'a str> {
+        self.stack
+            .iter()
+            .map(|&(cave, _)| self.caves.names[cave].as_str())
+            .chain([self.caves.names[self.caves.end].as_str()])
+            .collect()
+    }
+}
+
+impl<'a> Iterator for Paths<'a> {
+    type Item = Vec<&'a str>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let caves = self.caves;
+        loop {
+            let &mut (cave, ref mut idx) = self.stack.last_mut()?;
+
+            if let Some(&next) = caves.connections[cave].get(*idx) {
+                *idx += 1;
+                if next == caves.end {
+                    return Some(self.path());
+                }
+                if self.can_visit(next) {
+                    self.visits[next] += 1;
+                    if !caves.big[next] && self.visits[next] > 1 {
+                        self.repeated = Some(next);
+                    }
+                    self.stack.push((next, 0));
+                }
+            } else {
+                self.stack.pop();
+                self.visits[cave] -= 1;
+                if self.repeated == Some(cave) && self.visits[cave] == 1 {
+                    self.repeated = None;
+                }
+            }
+        }
+    }
 }
 
 fn part1(input: &Input) -> usize {
-    count_paths(input, false)
+    input.count_paths(VisitRule { max_visits: 1 })
 }
 
 fn part2(input: &Input) -> usize {
-    count_paths(input, true)
+    input.count_paths(VisitRule { max_visits: 2 })
 }
 
 fn main() -> Result<()> {
     measure(|| {
         let input = input()?;
+        if let Some(file) = flag("--paths") {
+            let writer = io::BufWriter::new(File::create(file)?);
+            input.export_paths(VisitRule { max_visits: 1 }, writer)?;
+        }
+        // `--dot caves.dot` draws the caves, with `--highlight start,A,end` one path through them
+        // or with `--counts` how many part 1 paths use each connection.
+        if let Some(file) = flag("--dot") {
+            let highlight = flag("--highlight");
+            let path = highlight
+                .iter()
+                .flat_map(|p| p.split(','))
+                .collect::<Vec<_>>();
+            let overlay = if highlight.is_some() {
+                Overlay::Path(&path)
+            } else if env::args().any(|arg| arg == "--counts") {
+                Overlay::Multiplicity(VisitRule { max_visits: 1 })
+            } else {
+                Overlay::None
+            };
+            fs::write(file, input.to_dot(overlay)?)?;
+        }
         println!("Part1: {}", part1(&input));
         println!("Part2: {}", part2(&input));
         Ok(())
@@ -103,20 +347,23 @@ fn main() -> Result<()> {
 }
 
 impl FromStr for Connection {
-    type Err = ParseIntError;
+    type Err = anyhow::Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let splits = s.split('-').collect::<Vec<_>>();
+        let (a, b) = s
+            .split_once('-')
+            .ok_or_else(|| anyhow!("Invalid connection {}", s))?;
         Ok(Connection {
-            caves: [splits[0].to_owned(), splits[1].to_owned()],
+            caves: [a.to_owned(), b.to_owned()],
         })
     }
 }
 
 fn read_input<R: Read>(reader: BufReader<R>) -> Result<Input> {
-    reader
+    let connections = reader
         .lines()
-        .map(|line| Ok(line?.parse::<Connection>()?))
-        .collect()
+        .map(|line| line?.parse::<Connection>())
+        .collect::<Result<Vec<_>>>()?;
+    CaveSystem::new(&connections)
 }
 
 fn input() -> Result<Input> {
@@ -130,6 +377,7 @@ fn input() -> Result<Input> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::HashSet;
 
     const INPUT: &'static str = "
         start-A
@@ -162,4 +410,97 @@ mod tests {
         assert_eq!(part2(&as_input(INPUT)?), 36);
         Ok(())
     }
+
+    const LARGER_INPUT: &'static str = "
+        dc-end
+        HN-start
+        start-kj
+        dc-start
+        dc-HN
+        LN-dc
+        HN-end
+        kj-sa
+        kj-HN
+        kj-dc";
+
+    #[test]
+    fn test_paths() -> Result<()> {
+        for (input, counts) in [(INPUT, [10, 36, 83]), (LARGER_INPUT, [19, 103, 305])] {
+            let input = as_input(input)?;
+            for (max_visits, count) in (1..).zip(counts) {
+                let rule = VisitRule { max_visits };
+                assert_eq!(input.count_paths(rule), count);
+
+                let paths = input.paths(rule).collect::<HashSet<_>>();
+                assert_eq!(paths.len(), count);
+                assert!(paths
+                    .iter()
+                    .all(|p| p[0] == "start" && p[p.len() - 1] == "end"));
+            }
+        }
+
+        let mut exported = vec![];
+        as_input(INPUT)?.export_paths(VisitRule { max_visits: 1 }, &mut exported)?;
+        let exported = String::from_utf8(exported)?;
+        assert_eq!(exported.lines().count(), 10);
+        assert!(exported.lines().any(|l| l == "start,A,b,A,c,A,end"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_to_dot() -> Result<()> {
+        let input = as_input("\nstart-A\nA-b\nA-end\nb-end")?;
+
+        assert_eq!(
+            input.to_dot(Overlay::None)?,
+            [
+                "graph caves {",
+                "    \"start\" [shape=ellipse, style=filled, fillcolor=palegreen];",
+                "    \"A\" [shape=box];",
+                "    \"b\" [shape=ellipse];",
+                "    \"end\" [shape=ellipse, style=filled, fillcolor=salmon];",
+                "    \"start\" -- \"A\";",
+                "    \"A\" -- \"b\";",
+                "    \"A\" -- \"end\";",
+                "    \"b\" -- \"end\";",
+                "}\n",
+            ]
+            .join("\n")
+        );
+
+        let dot = input.to_dot(Overlay::Path(&["start", "A", "end"]))?;
+        assert!(dot.contains("\"A\" -- \"end\" [color=blue, penwidth=2];"));
+        assert!(dot.contains("\"A\" -- \"b\";"));
+
+        // start,A,end start,A,b,end start,A,b,A,end
+        let dot = input.to_dot(Overlay::Multiplicity(VisitRule { max_visits: 1 }))?;
+        assert!(dot.contains("\"start\" -- \"A\" [label=\"3\", weight=3];"));
+        assert!(dot.contains("\"A\" -- \"b\" [label=\"3\", weight=3];"));
+        assert!(dot.contains("\"b\" -- \"end\" [label=\"1\", weight=1];"));
+
+        assert!(input.to_dot(Overlay::Path(&["start", "x", "end"])).is_err());
+        assert!(input.to_dot(Overlay::Path(&["start", "b", "end"])).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_many_caves() -> Result<()> {
+        // A chain of 300 small caves with a shortcut from start to end
+        let mut connections = (0..300)
+            .map(|i| Connection {
+                caves: [format!("c{}", i), format!("c{}", i + 1)],
+            })
+            .collect::<Vec<_>>();
+        connections.push("start-c0".parse()?);
+        connections.push("c300-end".parse()?);
+        connections.push("start-end".parse()?);
+
+        let caves = CaveSystem::new(&connections)?;
+        assert_eq!(caves.count_paths(VisitRule { max_visits: 1 }), 2);
+        assert_eq!(caves.paths(VisitRule { max_visits: 1 }).count(), 2);
+
+        assert!(as_input("\nstart-A\nA-B\nB-end").is_err());
+        assert!(as_input("\nstart-a").is_err());
+        Ok(())
+    }
 }
@@ -1,13 +1,17 @@
+use std::cmp::max;
 use std::collections::{HashSet, VecDeque};
 use std::env;
+use std::fmt;
 use std::fs::File;
 use std::io::prelude::*;
 use std::io::BufReader;
 use std::str::FromStr;
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
+use num_bigint::BigUint;
 
-use utils::measure;
+use utils::combinatorics::product;
+use utils::{flag, measure, ocr};
 
 type Input = TransparentPaper;
 
@@ -15,6 +19,8 @@ type Input = TransparentPaper;
 struct TransparentPaper {
     dots: HashSet<Pos>,
     folds: VecDeque<Fold>,
+    width: usize,
+    height: usize,
 }
 
 #[derive(Debug, Hash, Eq, PartialEq, Clone, Copy)]
@@ -23,58 +29,192 @@ struct Pos {
     y: usize,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 enum Fold {
     Vertical(usize),
     Horizontal(usize),
 }
 
+/// The state of the paper after a fold.
+#[derive(Debug, PartialEq)]
+struct FoldStep {
+    fold: Fold,
+    dots: usize,
+    /// Bounding box of the dots as its top left and bottom right positions.
+    bounds: Option<(Pos, Pos)>,
+    width: usize,
+    height: usize,
+}
+
+/// Folds a single coordinate of a paper `len` long along `line`. The longer of the two sides
+/// decides the length after the fold so when the folded side is the longer one the result is
+/// shifted to keep every coordinate within the paper.
+fn fold_coord(c: usize, line: usize, len: usize) -> Result<usize> {
+    if c == line {
+        return Err(anyhow!("Dot on fold line {}", line));
+    }
+    let folded_len = max(line, len.saturating_sub(line + 1));
+    let shift = folded_len - line;
+    Ok(if c < line {
+        c + shift
+    } else {
+        line + shift - (c - line)
+    })
+}
+
+/// The positions along an axis `len` long that could fold into `c`, the inverse of `fold_coord`.
+fn unfold_coord(c: usize, line: usize, len: usize) -> Vec<usize> {
+    let folded_len = max(line, len.saturating_sub(line + 1));
+    let shift = folded_len - line;
+    let mut coords = vec![];
+    if c >= shift && c - shift < line {
+        coords.push(c - shift);
+    }
+    if c < line + shift && line + (line + shift - c) < len {
+        coords.push(line + (line + shift - c));
+    }
+    coords
+}
+
+/// Every dot set that could have produced the folded dots, each folded dot coming from either
+/// or both of its possible positions.
+#[derive(Debug)]
+struct Unfolding {
+    choices: Vec<Vec<HashSet<Pos>>>,
+}
+
+impl Unfolding {
+    /// Each folded dot multiplies the count by its number of choices, so it gets huge quickly.
+    fn count(&self) -> BigUint {
+        self.choices
+            .iter()
+            .map(|c| BigUint::from(c.len()))
+            .product()
+    }
+
+    fn dot_sets(&self) -> impl Iterator<Item = HashSet<Pos>> {
+        product(self.choices.clone()).map(|choice| choice.into_iter().flatten().collect())
+    }
+}
+
 impl TransparentPaper {
-    fn fold(&mut self) -> Option<()> {
-        self.folds.pop_front().map(|fold| {
-            match fold {
-                Fold::Vertical(fold_x) => {
-                    self.fold_decide_translate(
-                        |dot| dot.x <= fold_x,
-                        |Pos { x, y }| Pos {
-                            x: fold_x - (x - fold_x),
-                            y,
-                        },
-                    );
-                }
-                Fold::Horizontal(fold_y) => {
-                    self.fold_decide_translate(
-                        |dot| dot.y <= fold_y,
-                        |Pos { x, y }| Pos {
-                            x,
-                            y: fold_y - (y - fold_y),
-                        },
-                    );
-                }
+    fn new(dots: HashSet<Pos>, folds: VecDeque<Fold>) -> TransparentPaper {
+        let (width, height) = dots
+            .iter()
+            .fold((0, 0), |(w, h), pos| (max(w, pos.x + 1), max(h, pos.y + 1)));
+        TransparentPaper {
+            dots,
+            folds,
+            width,
+            height,
+        }
+    }
+
+    /// Applies the next fold, returns false when there are no folds left.
+    fn fold(&mut self) -> Result<bool> {
+        let fold = match self.folds.pop_front() {
+            Some(fold) => fold,
+            None => return Ok(false),
+        };
+
+        let (width, height) = (self.width, self.height);
+        self.dots = self
+            .dots
+            .iter()
+            .map(|&Pos { x, y }| {
+                Ok(match fold {
+                    Fold::Vertical(fold_x) => Pos {
+                        x: fold_coord(x, fold_x, width)?,
+                        y,
+                    },
+                    Fold::Horizontal(fold_y) => Pos {
+                        x,
+                        y: fold_coord(y, fold_y, height)?,
+                    },
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        match fold {
+            Fold::Vertical(fold_x) => self.width = max(fold_x, width.saturating_sub(fold_x + 1)),
+            Fold::Horizontal(fold_y) => {
+                self.height = max(fold_y, height.saturating_sub(fold_y + 1))
             }
-            ()
-        })
+        }
+        Ok(true)
     }
 
-    fn fold_decide_translate(
-        &mut self,
-        fold_decide_fn: impl Fn(&&Pos) -> bool,
-        fold_translate_fn: impl Fn(Pos) -> Pos,
-    ) {
-        let (mut stay, to_fold): (HashSet<Pos>, HashSet<Pos>) =
-            self.dots.iter().partition(fold_decide_fn);
+    fn bounds(&self) -> Option<(Pos, Pos)> {
+        let first = *self.dots.iter().next()?;
+        Some(self.dots.iter().fold((first, first), |(min, max), pos| {
+            (
+                Pos {
+                    x: min.x.min(pos.x),
+                    y: min.y.min(pos.y),
+                },
+                Pos {
+                    x: max.x.max(pos.x),
+                    y: max.y.max(pos.y),
+                },
+            )
+        }))
+    }
 
-        for pos in to_fold {
-            stay.insert(fold_translate_fn(pos));
+    /// Applies all folds to a copy of the paper, recording the state after each one.
+    fn history(&self) -> Result<Vec<FoldStep>> {
+        let mut tpaper = self.clone();
+        let mut history = vec![];
+        while let Some(&fold) = tpaper.folds.front() {
+            tpaper.fold()?;
+            history.push(FoldStep {
+                fold,
+                dots: tpaper.dots.len(),
+                bounds: tpaper.bounds(),
+                width: tpaper.width,
+                height: tpaper.height,
+            });
         }
+        Ok(history)
+    }
+
+    /// Possible dots before `fold` was applied to a paper `width` x `height` in size.
+    fn unfold(&self, fold: Fold, width: usize, height: usize) -> Unfolding {
+        let choices = self
+            .dots
+            .iter()
+            .map(|&Pos { x, y }| {
+                let candidates = match fold {
+                    Fold::Vertical(fold_x) => unfold_coord(x, fold_x, width)
+                        .into_iter()
+                        .map(|x| Pos { x, y })
+                        .collect::<Vec<_>>(),
+                    Fold::Horizontal(fold_y) => unfold_coord(y, fold_y, height)
+                        .into_iter()
+                        .map(|y| Pos { x, y })
+                        .collect::<Vec<_>>(),
+                };
+                let mut choice = candidates
+                    .iter()
+                    .map(|&pos| [pos].into_iter().collect::<HashSet<_>>())
+                    .collect::<Vec<_>>();
+                if candidates.len() > 1 {
+                    choice.push(candidates.into_iter().collect());
+                }
+                choice
+            })
+            .collect();
+        Unfolding { choices }
+    }
 
-        self.dots = stay;
+    fn max_pos(&self) -> (usize, usize) {
+        self.dots.iter().fold((0, 0), |(mx, my), pos| {
+            (std::cmp::max(mx, pos.x), std::cmp::max(my, pos.y))
+        })
     }
 
+    #[cfg(feature = "print")]
     fn print(&self) {
-        let (max_x, max_y) = self.dots.iter().fold((0, 0), |(mx, my), pos| {
-            (std::cmp::max(mx, pos.x), std::cmp::max(my, pos.y))
-        });
+        let (max_x, max_y) = self.max_pos();
 
         for y in 0..=max_y {
             for x in 0..=max_x {
@@ -84,26 +224,82 @@ impl TransparentPaper {
             println!();
         }
     }
+
+    fn read_code(&self) -> Result<String> {
+        let (max_x, _) = self.max_pos();
+        ocr::read_letters(max_x + 1, |x, y| self.dots.contains(&Pos { x, y }))
+    }
 }
 
-fn part1(input: &Input) -> usize {
+fn part1(input: &Input) -> Result<usize> {
     let mut tpaper = input.clone();
-    tpaper.fold();
-    tpaper.dots.len()
+    tpaper.fold()?;
+    Ok(tpaper.dots.len())
 }
 
-fn part2(input: &Input) {
+fn part2(input: &Input) -> Result<String> {
     let mut tpaper = input.clone();
-    while let Some(_) = tpaper.fold() {}
+    while tpaper.fold()? {}
+
+    #[cfg(feature = "print")]
     tpaper.print();
+
+    tpaper.read_code()
+}
+
+impl fmt::Display for Fold {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Fold::Vertical(x) => write!(f, "x={}", x),
+            Fold::Horizontal(y) => write!(f, "y={}", y),
+        }
+    }
+}
+
+fn print_history(input: &Input) -> Result<()> {
+    for step in input.history()? {
+        print!(
+            "Fold {}: {} dots on {}x{}",
+            step.fold, step.dots, step.width, step.height
+        );
+        match step.bounds {
+            Some((min, max)) => println!(", within {},{}..{},{}", min.x, min.y, max.x, max.y),
+            None => println!(),
+        }
+    }
+    Ok(())
+}
+
+/// Prints how many dot sets could have been folded into the final paper, and the sizes of the
+/// first `n` of them.
+fn print_unfold(input: &Input, n: usize) -> Result<()> {
+    let mut tpaper = input.clone();
+    while tpaper.folds.len() > 1 {
+        tpaper.fold()?;
+    }
+    let fold = *tpaper.folds.front().context("No folds to undo")?;
+    let (width, height) = (tpaper.width, tpaper.height);
+    tpaper.fold()?;
+
+    let unfolding = tpaper.unfold(fold, width, height);
+    println!("Unfoldings of {}: {}", fold, unfolding.count());
+    for dots in unfolding.dot_sets().take(n) {
+        println!("  {} dots", dots.len());
+    }
+    Ok(())
 }
 
 fn main() -> Result<()> {
     measure(|| {
         let input = input()?;
-        println!("Part1: {}", part1(&input));
-        println!("Part2:");
-        part2(&input);
+        if env::args().any(|arg| arg == "--history") {
+            print_history(&input)?;
+        }
+        if let Some(n) = flag("--unfold") {
+            print_unfold(&input, n.parse()?)?;
+        }
+        println!("Part1: {}", part1(&input)?);
+        println!("Part2: {}", part2(&input)?);
         Ok(())
     })
 }
@@ -129,7 +325,7 @@ impl FromStr for Fold {
         Ok(match axis {
             "x" => Fold::Vertical(val),
             "y" => Fold::Horizontal(val),
-            _ => unreachable!(),
+            _ => return Err(anyhow!("Invalid fold axis {}", axis)),
         })
     }
 }
@@ -155,7 +351,7 @@ fn read_input<R: Read>(reader: BufReader<R>) -> Result<Input> {
         folds.push_back(line.parse::<Fold>()?);
     }
 
-    Ok(TransparentPaper { dots, folds })
+    Ok(TransparentPaper::new(dots, folds))
 }
 
 fn input() -> Result<Input> {
@@ -206,7 +402,120 @@ mod tests {
 
     #[test]
     fn test_part1() -> Result<()> {
-        assert_eq!(part1(&as_input(INPUT)?), 17);
+        assert_eq!(part1(&as_input(INPUT)?)?, 17);
+        Ok(())
+    }
+
+    #[test]
+    fn test_part2() -> Result<()> {
+        // The example folds into a square, which isn't a letter
+        assert!(part2(&as_input(INPUT)?).is_err());
+
+        // "LJ" drawn upside down below the fold line
+        let rows = [
+            "#......##",
+            "#.......#",
+            "#.......#",
+            "#.......#",
+            "#....#..#",
+            "####..##.",
+        ];
+        let mut dots = HashSet::new();
+        for (y, row) in rows.iter().enumerate() {
+            for (x, _) in row.chars().enumerate().filter(|&(_, c)| c == '#') {
+                dots.insert(Pos { x, y: 12 - y });
+            }
+        }
+        let input = TransparentPaper::new(dots, [Fold::Horizontal(6)].into_iter().collect());
+        assert_eq!(part2(&input)?, "LJ");
+        Ok(())
+    }
+
+    #[test]
+    fn test_history() -> Result<()> {
+        let history = as_input(INPUT)?.history()?;
+        assert_eq!(
+            history,
+            vec![
+                FoldStep {
+                    fold: Fold::Horizontal(7),
+                    dots: 17,
+                    bounds: Some((Pos { x: 0, y: 0 }, Pos { x: 10, y: 4 })),
+                    width: 11,
+                    height: 7,
+                },
+                FoldStep {
+                    fold: Fold::Vertical(5),
+                    dots: 16,
+                    bounds: Some((Pos { x: 0, y: 0 }, Pos { x: 4, y: 4 })),
+                    width: 5,
+                    height: 7,
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_asymmetric_fold() -> Result<()> {
+        // The right side is longer than the left so everything ends up shifted right
+        let dots = [(0, 0), (1, 0), (6, 0)]
+            .into_iter()
+            .map(|(x, y)| Pos { x, y })
+            .collect::<HashSet<_>>();
+        let mut tpaper =
+            TransparentPaper::new(dots.clone(), [Fold::Vertical(2)].into_iter().collect());
+        assert!(tpaper.fold()?);
+        assert!(!tpaper.fold()?);
+        assert_eq!(tpaper.width, 4);
+        assert_eq!(
+            tpaper.dots,
+            [(0, 0), (2, 0), (3, 0)]
+                .into_iter()
+                .map(|(x, y)| Pos { x, y })
+                .collect()
+        );
+
+        let unfolding = tpaper.unfold(Fold::Vertical(2), 7, 1);
+        // x = 0 only comes from 6 while 2 and 3 come from 0 or 4 and 1 or 3
+        assert_eq!(unfolding.count(), BigUint::from(9_u32));
+        assert!(unfolding.dot_sets().any(|d| d == dots));
+
+        let mut tpaper = TransparentPaper::new(dots, [Fold::Vertical(1)].into_iter().collect());
+        assert!(tpaper.fold().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_unfold_full_size() -> Result<()> {
+        // As big as a real puzzle input, with every folded dot coming from the left, the right
+        // or both sides.
+        let (width, height) = (1311, 895);
+        let dots = (0..120)
+            .map(|i| Pos {
+                x: i * 37 % width,
+                y: i * 101 % height,
+            })
+            .filter(|pos| pos.x != 655)
+            .collect::<HashSet<_>>();
+        let folds = [Fold::Vertical(655)].into_iter().collect::<VecDeque<_>>();
+        let mut tpaper = TransparentPaper::new(dots, folds.clone());
+        (tpaper.width, tpaper.height) = (width, height);
+        tpaper.fold()?;
+
+        let unfolding = tpaper.unfold(Fold::Vertical(655), width, height);
+        assert_eq!(
+            unfolding.count(),
+            BigUint::from(3_u32).pow(tpaper.dots.len() as u32)
+        );
+        assert!(unfolding.count() > BigUint::from(usize::MAX));
+
+        for dots in unfolding.dot_sets().take(100) {
+            let mut refolded = TransparentPaper::new(dots, folds.clone());
+            (refolded.width, refolded.height) = (width, height);
+            refolded.fold()?;
+            assert_eq!(refolded.dots, tpaper.dots);
+        }
         Ok(())
     }
 }
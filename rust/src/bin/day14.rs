@@ -1,14 +1,19 @@
-use std::collections::HashMap;
+use std::cmp::min;
+use std::collections::{BTreeMap, HashMap};
 use std::env;
 use std::fs::File;
 use std::io::prelude::*;
 use std::io::BufReader;
+use std::ops::Range;
 
 use anyhow::{Context, Result};
+use num_bigint::BigUint;
+use num_traits::Zero;
 
-use utils::measure;
+use utils::{flag, measure};
 
 type Pair = [char; 2];
+type Matrix = Vec<Vec<BigUint>>;
 
 #[derive(Debug)]
 struct Input {
@@ -16,46 +21,284 @@ struct Input {
     pair_insertion: HashMap<Pair, char>,
 }
 
-fn solve(input: &Input) -> (i64, i64) {
-    let mut pair_freqs = HashMap::<Pair, i64>::new();
-    for pair in input.polymer_template.windows(2) {
-        let pair = [pair[0], pair[1]];
-        *pair_freqs.entry(pair).or_default() += 1;
+impl Input {
+    /// Step count above which pair counts are computed by matrix exponentiation rather than
+    /// stepping through every insertion.
+    const MATRIX_STEPS: u64 = 1000;
+
+    /// All pairs that can ever occur, in a fixed order.
+    fn pairs(&self) -> Vec<Pair> {
+        let mut pairs = self
+            .polymer_template
+            .windows(2)
+            .map(|p| [p[0], p[1]])
+            .collect::<Vec<_>>();
+        for (pair, &insert_elem) in &self.pair_insertion {
+            pairs.extend([*pair, [pair[0], insert_elem], [insert_elem, pair[1]]]);
+        }
+        pairs.sort_unstable();
+        pairs.dedup();
+        pairs
     }
 
-    let mut results = vec![];
+    /// The pairs a pair turns into after one step, pairs without a rule stay as they are.
+    fn produces(&self, pair: Pair) -> Vec<Pair> {
+        match self.pair_insertion.get(&pair) {
+            Some(&insert_elem) => vec![[pair[0], insert_elem], [insert_elem, pair[1]]],
+            None => vec![pair],
+        }
+    }
 
-    for step in 1..=40 {
-        let mut next_pair_freqs = HashMap::<Pair, i64>::new();
-        for (pair, freq) in pair_freqs {
-            let insert_elem = input.pair_insertion[&pair];
-            let pair1 = [pair[0], insert_elem];
-            let pair2 = [insert_elem, pair[1]];
-            *next_pair_freqs.entry(pair1).or_default() += freq;
-            *next_pair_freqs.entry(pair2).or_default() += freq;
+    fn pair_counts(&self, steps: u64) -> HashMap<Pair, BigUint> {
+        let mut pair_counts = HashMap::<Pair, BigUint>::new();
+        for pair in self.polymer_template.windows(2) {
+            *pair_counts.entry([pair[0], pair[1]]).or_default() += 1_u32;
+        }
+
+        if steps > Input::MATRIX_STEPS {
+            return self.pair_counts_matrix(pair_counts, steps);
+        }
+
+        for _ in 0..steps {
+            let mut next_pair_counts = HashMap::<Pair, BigUint>::new();
+            for (pair, count) in pair_counts {
+                for next_pair in self.produces(pair) {
+                    *next_pair_counts.entry(next_pair).or_default() += &count;
+                }
+            }
+            pair_counts = next_pair_counts;
         }
 
-        pair_freqs = next_pair_freqs;
+        pair_counts
+    }
+
+    /// Raises the step transition matrix to the power of `steps` by repeated squaring, where
+    /// `matrix[j][i]` is how many of pair `j` one pair `i` turns into.
+    fn pair_counts_matrix(
+        &self,
+        pair_counts: HashMap<Pair, BigUint>,
+        steps: u64,
+    ) -> HashMap<Pair, BigUint> {
+        let pairs = self.pairs();
+        let index = pairs
+            .iter()
+            .enumerate()
+            .map(|(i, &p)| (p, i))
+            .collect::<HashMap<_, _>>();
+        let n = pairs.len();
 
-        if step == 10 || step == 40 {
-            let mut elem_pair_freqs = HashMap::<char, i64>::new();
-            for (pair, freq) in &pair_freqs {
-                for &c in pair {
-                    *elem_pair_freqs.entry(c).or_default() += freq;
+        let mul = |a: &Matrix, b: &Matrix| -> Matrix {
+            let mut c = vec![vec![BigUint::zero(); n]; n];
+            for i in 0..n {
+                for k in (0..n).filter(|&k| !a[i][k].is_zero()) {
+                    for j in (0..n).filter(|&j| !b[k][j].is_zero()) {
+                        c[i][j] += &a[i][k] * &b[k][j];
+                    }
                 }
             }
-            let elem_freqs = elem_pair_freqs
-                .into_iter()
-                .map(|(c, f)| (c, (f / 2) + (f % 2)))
+            c
+        };
+
+        let mut step = vec![vec![BigUint::zero(); n]; n];
+        for (i, &pair) in pairs.iter().enumerate() {
+            for next_pair in self.produces(pair) {
+                step[index[&next_pair]][i] += 1_u32;
+            }
+        }
+
+        let mut result = (0..n)
+            .map(|i| (0..n).map(|j| BigUint::from((i == j) as u32)).collect())
+            .collect::<Matrix>();
+        let mut steps = steps;
+        while steps > 0 {
+            if steps & 1 == 1 {
+                result = mul(&result, &step);
+            }
+            step = mul(&step, &step);
+            steps >>= 1;
+        }
+
+        let counts = pairs
+            .iter()
+            .map(|p| pair_counts.get(p).cloned().unwrap_or_default())
+            .collect::<Vec<_>>();
+        pairs
+            .iter()
+            .enumerate()
+            .map(|(j, &pair)| {
+                let count = (0..n).map(|i| &result[j][i] * &counts[i]).sum();
+                (pair, count)
+            })
+            .filter(|(_, count): &(Pair, BigUint)| !count.is_zero())
+            .collect()
+    }
+
+    /// Number of each element in the polymer after `steps` steps. Every element is the first of a
+    /// pair except the last one of the template, which never changes.
+    fn element_frequencies(&self, steps: u64) -> BTreeMap<char, BigUint> {
+        let mut freqs = BTreeMap::<char, BigUint>::new();
+        for (pair, count) in self.pair_counts(steps) {
+            *freqs.entry(pair[0]).or_default() += count;
+        }
+        if let Some(&last) = self.polymer_template.last() {
+            *freqs.entry(last).or_default() += 1_u32;
+        }
+        freqs
+    }
+}
+
+/// Looks into the polymer after a number of steps without building it, by following the pairs
+/// each pair expands into down to the wanted positions.
+struct PolymerQuery<'a> {
+    input: &'a Input,
+    steps: usize,
+    /// Segment lengths of every pair with a rule after each step, starting from step 0 and
+    /// ending at the step where they stop changing.
+    lengths: Vec<HashMap<Pair, u64>>,
+}
+
+impl<'a> PolymerQuery<'a> {
+    fn new(input: &'a Input, steps: usize) -> PolymerQuery<'a> {
+        let mut lengths = vec![input
+            .pair_insertion
+            .keys()
+            .map(|&pair| (pair, 1_u64))
+            .collect::<HashMap<_, _>>()];
+
+        // Lengths at most double each step, so they've either stopped growing or saturated
+        // after about 64 steps.
+        for _ in 0..steps {
+            let prev = &lengths[lengths.len() - 1];
+            let len = |pair| prev.get(&pair).copied().unwrap_or(1);
+            let next = input
+                .pair_insertion
+                .iter()
+                .map(|(&pair, &insert_elem)| {
+                    let left = len([pair[0], insert_elem]);
+                    (pair, left.saturating_add(len([insert_elem, pair[1]])))
+                })
                 .collect::<HashMap<_, _>>();
+            if &next == prev {
+                break;
+            }
+            lengths.push(next);
+        }
 
-            let max_freq = elem_freqs.values().max().unwrap_or(&0);
-            let min_freq = elem_freqs.values().min().unwrap_or(&0);
-            results.push(max_freq - min_freq);
+        PolymerQuery {
+            input,
+            steps,
+            lengths,
         }
     }
 
-    (results[0], results[1])
+    /// Length of what a pair expands into after `steps` steps, leaving out its last element as
+    /// that's the first element of the next pair. Saturates at `u64::MAX`.
+    fn segment_len(&self, pair: Pair, steps: usize) -> u64 {
+        let lengths = &self.lengths[min(steps, self.lengths.len() - 1)];
+        lengths.get(&pair).copied().unwrap_or(1)
+    }
+
+    fn len(&self) -> u64 {
+        let template = &self.input.polymer_template;
+        let mut len = template.len().min(1) as u64;
+        for pair in template.windows(2) {
+            len = len.saturating_add(self.segment_len([pair[0], pair[1]], self.steps));
+        }
+        len
+    }
+
+    fn char_at(&self, i: u64) -> Option<char> {
+        self.substring(i..i.saturating_add(1)).chars().next()
+    }
+
+    /// The elements at the positions in `range`, only expanding the pairs overlapping it.
+    fn substring(&self, range: Range<u64>) -> String {
+        let template = &self.input.polymer_template;
+        let mut substring = String::new();
+        let mut stack = vec![];
+
+        let mut offset = 0_u64;
+        for pair in template.windows(2) {
+            let pair = [pair[0], pair[1]];
+            stack.push((pair, self.steps, offset));
+            offset = offset.saturating_add(self.segment_len(pair, self.steps));
+        }
+        stack.reverse();
+
+        while let Some((pair, steps, offset)) = stack.pop() {
+            let len = self.segment_len(pair, steps);
+            if offset >= range.end || offset.saturating_add(len) <= range.start {
+                continue;
+            }
+
+            match self.input.pair_insertion.get(&pair) {
+                Some(&insert_elem) if steps > 0 => {
+                    let left = [pair[0], insert_elem];
+                    let left_len = self.segment_len(left, steps - 1);
+                    stack.push((
+                        [insert_elem, pair[1]],
+                        steps - 1,
+                        offset.saturating_add(left_len),
+                    ));
+                    stack.push((left, steps - 1, offset));
+                }
+                _ => substring.push(pair[0]),
+            }
+        }
+
+        if let Some(&last) = template.last() {
+            if range.contains(&offset) {
+                substring.push(last);
+            }
+        }
+        substring
+    }
+}
+
+/// Lists the elements from most to least common, one `element: count` per line.
+fn frequency_table(freqs: &BTreeMap<char, BigUint>) -> String {
+    let mut freqs = freqs.iter().collect::<Vec<_>>();
+    freqs.sort_by(|(e1, f1), (e2, f2)| f2.cmp(f1).then(e1.cmp(e2)));
+    freqs
+        .iter()
+        .map(|(e, f)| format!("{}: {}\n", e, f))
+        .collect()
+}
+
+fn most_minus_least_common(input: &Input, steps: u64) -> BigUint {
+    let freqs = input.element_frequencies(steps);
+
+    #[cfg(feature = "print")]
+    print!("After step {}:\n{}", steps, frequency_table(&freqs));
+
+    match (freqs.values().max(), freqs.values().min()) {
+        (Some(max), Some(min)) => max - min,
+        _ => BigUint::zero(),
+    }
+}
+
+fn solve(input: &Input) -> (BigUint, BigUint) {
+    (
+        most_minus_least_common(input, 10),
+        most_minus_least_common(input, 40),
+    )
+}
+
+/// Prints the polymer after `steps` steps at `range`, either `start..end` or a single position.
+fn print_range(input: &Input, steps: usize, range: &str) -> Result<()> {
+    let query = PolymerQuery::new(input, steps);
+    println!("Length after step {}: {}", steps, query.len());
+    match range.split_once("..") {
+        Some((start, end)) => {
+            let substring = query.substring(start.parse()?..end.parse()?);
+            println!("{}: {}", range, substring);
+        }
+        None => match query.char_at(range.parse()?) {
+            Some(c) => println!("{}: {}", range, c),
+            None => println!("{}: past the end", range),
+        },
+    }
+    Ok(())
 }
 
 fn main() -> Result<()> {
@@ -64,6 +307,15 @@ fn main() -> Result<()> {
         let (part1, part2) = solve(&input);
         println!("Part1: {}", part1);
         println!("Part2: {}", part2);
+        if let Some(steps) = flag("--steps") {
+            let steps = steps.parse()?;
+            let freqs = input.element_frequencies(steps);
+            print!("After step {}:\n{}", steps, frequency_table(&freqs));
+        }
+        if let Some(range) = flag("--range") {
+            let steps = flag("--steps").map_or(Ok(10), |steps| steps.parse())?;
+            print_range(&input, steps, &range)?;
+        }
         Ok(())
     })
 }
@@ -151,13 +403,74 @@ mod tests {
 
     #[test]
     fn test_part1() -> Result<()> {
-        assert_eq!(solve(&as_input(INPUT)?).0, 1588);
+        assert_eq!(solve(&as_input(INPUT)?).0, BigUint::from(1588_u32));
         Ok(())
     }
 
     #[test]
     fn test_part2() -> Result<()> {
-        assert_eq!(solve(&as_input(INPUT)?).1, 2188189693529);
+        assert_eq!(solve(&as_input(INPUT)?).1, BigUint::from(2188189693529_u64));
+        Ok(())
+    }
+
+    #[test]
+    fn test_element_frequencies() -> Result<()> {
+        let input = as_input(INPUT)?;
+        assert_eq!(
+            frequency_table(&input.element_frequencies(10)),
+            "B: 1749\nN: 865\nC: 298\nH: 161\n"
+        );
+
+        let pair_counts = input.pair_counts(40);
+        let initial = input.pair_counts(0);
+        assert_eq!(input.pair_counts_matrix(initial, 40), pair_counts);
+
+        let freqs = input.element_frequencies(5000);
+        let len = freqs.values().sum::<BigUint>();
+        assert_eq!(len, (BigUint::from(2_u32).pow(5000) * 3_u32) + 1_u32);
+        Ok(())
+    }
+
+    #[test]
+    fn test_pairs_without_rules() -> Result<()> {
+        let input = as_input("\nABA\n\nAB -> C")?;
+        assert_eq!(
+            frequency_table(&input.element_frequencies(2)),
+            "A: 2\nB: 1\nC: 1\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_polymer_query() -> Result<()> {
+        let input = as_input(INPUT)?;
+
+        let query = PolymerQuery::new(&input, 2);
+        assert_eq!(query.len(), 13);
+        assert_eq!(query.substring(0..13), "NBCCNBBBCBHCB");
+        assert_eq!(query.substring(3..7), "CNBB");
+        assert_eq!(query.substring(10..20), "HCB");
+        assert_eq!(query.char_at(12), Some('B'));
+        assert_eq!(query.char_at(13), None);
+
+        let query = PolymerQuery::new(&input, 4);
+        let len = query.len();
+        assert_eq!(
+            query.substring(0..len),
+            "NBBNBNBBCCNBCNCCNBBNBBNBBBNBBNBBCBHCBHHNHCBBCBHCB"
+        );
+
+        let query = PolymerQuery::new(&input, 40);
+        assert_eq!(query.len(), 3 * (1 << 40) + 1);
+        assert_eq!(query.char_at(0), Some('N'));
+        assert_eq!(query.char_at(3 * (1 << 40)), Some('B'));
+        assert_eq!(query.substring(0..5), "NBBNB");
+
+        let query = PolymerQuery::new(&input, 500_000);
+        assert!(query.lengths.len() < 100);
+        assert_eq!(query.len(), u64::MAX);
+        assert_eq!(query.char_at(0), Some('N'));
+        assert_eq!(query.substring(1..3), "BB");
         Ok(())
     }
 }
@@ -1,11 +1,10 @@
-use std::cmp::Ordering;
-use std::collections::{BinaryHeap, HashMap};
+use std::collections::HashSet;
 use std::env;
 use std::fs::File;
 use std::io::prelude::*;
 use std::io::BufReader;
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 
 use utils::measure;
 
@@ -14,27 +13,149 @@ type Input = Cavern;
 #[derive(Debug)]
 struct Cavern {
     risk_levels: Vec<Vec<u8>>,
+    tiling: Tiling,
+}
+
+/// How the scanned area repeats to form the full cavern, each tile to the right or down
+/// having its risk levels raised by `increment`, wrapping around from `max_risk` to `min_risk`.
+#[derive(Debug, Clone, Copy)]
+struct Tiling {
+    factor_x: usize,
+    factor_y: usize,
+    increment: u8,
+    min_risk: u8,
+    max_risk: u8,
+}
+
+impl Tiling {
+    const NONE: Tiling = Tiling {
+        factor_x: 1,
+        factor_y: 1,
+        increment: 1,
+        min_risk: 1,
+        max_risk: 9,
+    };
+
+    fn new(
+        factor_x: usize,
+        factor_y: usize,
+        increment: u8,
+        min_risk: u8,
+        max_risk: u8,
+    ) -> Result<Tiling> {
+        if factor_x == 0 || factor_y == 0 {
+            return Err(anyhow!("Tiling factors must be at least 1"));
+        }
+        if min_risk > max_risk {
+            return Err(anyhow!(
+                "Minimum risk level {} is above the maximum {}",
+                min_risk,
+                max_risk
+            ));
+        }
+        // Raising by a whole range of risk levels or more is the same as raising by less.
+        if increment > max_risk - min_risk {
+            return Err(anyhow!(
+                "Increment {} is too large for risk levels {} to {}",
+                increment,
+                min_risk,
+                max_risk
+            ));
+        }
+        Ok(Tiling {
+            factor_x,
+            factor_y,
+            increment,
+            min_risk,
+            max_risk,
+        })
+    }
+
+    fn square(factor: usize) -> Result<Tiling> {
+        Tiling::new(factor, factor, 1, 1, 9)
+    }
+
+    fn check_risk_level(&self, risk_level: u8) -> Result<()> {
+        if risk_level < self.min_risk || risk_level > self.max_risk {
+            return Err(anyhow!(
+                "Risk level {} is outside {} to {}",
+                risk_level,
+                self.min_risk,
+                self.max_risk
+            ));
+        }
+        Ok(())
+    }
+
+    /// Expects `risk_level` to be within the tiling's range.
+    fn risk_level(&self, risk_level: u8, tile_x: usize, tile_y: usize) -> u8 {
+        let range = (self.max_risk - self.min_risk) as usize + 1;
+        let raised =
+            (risk_level - self.min_risk) as usize + self.increment as usize * (tile_x + tile_y);
+        self.min_risk + (raised % range) as u8
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Movement {
+    diagonal: bool,
+}
+
+impl Movement {
+    const ORTHOGONAL: Movement = Movement { diagonal: false };
 }
 
 impl Cavern {
+    fn new(risk_levels: Vec<Vec<u8>>, tiling: Tiling) -> Result<Cavern> {
+        let width = risk_levels.first().map_or(0, |row| row.len());
+        if width == 0 || risk_levels.iter().any(|row| row.len() != width) {
+            return Err(anyhow!("The scanned area isn't a non-empty rectangle"));
+        }
+        for risk_level in risk_levels.iter().flatten() {
+            tiling.check_risk_level(*risk_level)?;
+        }
+        Ok(Cavern {
+            risk_levels,
+            tiling,
+        })
+    }
+
     fn width(&self) -> usize {
-        self.risk_levels[0].len()
+        self.risk_levels[0].len() * self.tiling.factor_x
     }
 
     fn height(&self) -> usize {
-        self.risk_levels.len()
+        self.risk_levels.len() * self.tiling.factor_y
     }
 
+    /// Risk level anywhere in the tiled cavern, computed from the scanned tile.
     fn risk_level(&self, pos: &Pos) -> u8 {
-        self.risk_levels[pos.y][pos.x]
+        let w = self.risk_levels[0].len();
+        let h = self.risk_levels.len();
+        self.tiling
+            .risk_level(self.risk_levels[pos.y % h][pos.x % w], pos.x / w, pos.y / h)
     }
 
-    fn adjacent(&self, x: usize, y: usize) -> Vec<Pos> {
+    fn adjacent(&self, x: usize, y: usize, movement: Movement) -> Vec<Pos> {
         let x = x as i32;
         let y = y as i32;
         let w = self.width() as i32;
         let h = self.height() as i32;
-        [(1_i32, 0_i32), (0, 1), (-1, 0), (0, -1)]
+        let steps: &[(i32, i32)] = if movement.diagonal {
+            &[
+                (1, 0),
+                (1, 1),
+                (0, 1),
+                (-1, 1),
+                (-1, 0),
+                (-1, -1),
+                (0, -1),
+                (1, -1),
+            ]
+        } else {
+            &[(1, 0), (0, 1), (-1, 0), (0, -1)]
+        };
+        steps
             .iter()
             .map(|&(rx, ry)| (x + rx, y + ry))
             .filter(|&(x, y)| x >= 0 && y >= 0 && x < w && y < h)
@@ -42,116 +163,116 @@ impl Cavern {
             .collect()
     }
 
-    fn lowest_risk_path(&self) -> Vec<Pos> {
-        #[derive(Clone, Debug)]
-        struct Queued {
-            pos: Pos,
-            risk_level: i32,
-        }
-        impl Ord for Queued {
-            fn cmp(&self, other: &Queued) -> Ordering {
-                other.risk_level.cmp(&self.risk_level)
-            }
-        }
-        impl PartialOrd for Queued {
-            fn partial_cmp(&self, other: &Queued) -> Option<Ordering> {
-                Some(self.cmp(other))
-            }
-        }
-        impl PartialEq for Queued {
-            fn eq(&self, other: &Queued) -> bool {
-                self.risk_level == other.risk_level
-            }
-        }
-        impl Eq for Queued {}
-
-        let start_pos = Pos::new(0, 0);
-        let end_pos = Pos::new(self.width() - 1, self.height() - 1);
-
-        let mut heap = BinaryHeap::new();
-        heap.push(Queued {
-            pos: start_pos,
-            risk_level: 0,
-        });
-
-        let mut visited = HashMap::<Pos, (i32, Option<Pos>)>::new();
-        visited.insert(Pos::new(0, 0), (0, None));
+    fn corners(&self) -> (Pos, Pos) {
+        (
+            Pos::new(0, 0),
+            Pos::new(self.width() - 1, self.height() - 1),
+        )
+    }
 
-        while let Some(Queued { pos, risk_level }) = heap.pop() {
-            if pos == end_pos {
-                break;
+    /// Dijkstra's algorithm using a bucket queue (Dial's algorithm), which works well since risk
+    /// levels are small. Pending positions are kept in one bucket per total risk modulo the
+    /// highest risk level plus one, as that's how far ahead of the current one they can be.
+    fn lowest_risk_path(
+        &self,
+        start_pos: Pos,
+        end_pos: Pos,
+        movement: Movement,
+    ) -> Result<Vec<Pos>> {
+        let w = self.width();
+        for pos in [start_pos, end_pos] {
+            if pos.x >= w || pos.y >= self.height() {
+                return Err(anyhow!(
+                    "{:?} is outside the {}x{} cavern",
+                    pos,
+                    w,
+                    self.height()
+                ));
             }
+        }
+        let idx = |pos: &Pos| pos.y * w + pos.x;
 
-            for adj in self.adjacent(pos.x, pos.y) {
-                let risk_level = risk_level + self.risk_level(&adj) as i32;
+        let max_risk = self
+            .risk_levels
+            .iter()
+            .flatten()
+            .copied()
+            .max()
+            .unwrap_or(0)
+            .max(self.tiling.max_risk) as usize;
+        let bucket_count = max_risk + 1;
+        let mut buckets = vec![vec![]; bucket_count];
+        let mut pending = 1;
+
+        let mut total_risks = vec![u32::MAX; w * self.height()];
+        let mut previous = vec![None; w * self.height()];
+
+        total_risks[idx(&start_pos)] = 0;
+        buckets[0].push(start_pos);
+
+        let mut risk_level = 0_u32;
+        while pending > 0 {
+            let bucket = risk_level as usize % bucket_count;
+            while let Some(pos) = buckets[bucket].pop() {
+                pending -= 1;
+                if total_risks[idx(&pos)] != risk_level {
+                    continue;
+                }
+                if pos == end_pos {
+                    pending = 0;
+                    break;
+                }
 
-                if let Some(&(visited_rl, _)) = visited.get(&adj) {
-                    if risk_level >= visited_rl {
-                        continue;
+                for adj in self.adjacent(pos.x, pos.y, movement) {
+                    let adj_risk_level = risk_level + self.risk_level(&adj) as u32;
+                    if adj_risk_level < total_risks[idx(&adj)] {
+                        total_risks[idx(&adj)] = adj_risk_level;
+                        previous[idx(&adj)] = Some(pos);
+                        buckets[adj_risk_level as usize % bucket_count].push(adj);
+                        pending += 1;
                     }
                 }
-
-                visited.insert(adj, (risk_level, Some(pos)));
-
-                heap.push(Queued {
-                    pos: adj,
-                    risk_level,
-                });
             }
+            risk_level += 1;
         }
 
         let mut path = vec![];
         let mut next_pos = Some(end_pos);
         while let Some(pos) = next_pos {
             path.push(pos);
-            next_pos = visited[&pos].1;
+            next_pos = previous[idx(&pos)];
         }
         path.reverse();
-        path
+        Ok(path)
     }
 
-    fn expand(&self, times: usize) -> Self {
-        let w = self.width();
-        let h = self.height();
-        let mut risk_levels = vec![];
-
-        for row in &self.risk_levels {
-            risk_levels.push(
-                row.iter()
-                    .cycle()
-                    .take(w * times)
-                    .cloned()
-                    .collect::<Vec<u8>>(),
-            );
-        }
-
-        risk_levels.append(
-            &mut risk_levels
-                .iter()
-                .cycle()
-                .take(h * (times - 1))
-                .cloned()
-                .collect::<Vec<Vec<u8>>>(),
-        );
-
-        for ay in 0..times {
-            for ax in 0..times {
-                let add = ay as u8 + ax as u8;
-                let tx = ax * self.width();
-                let ty = ay * self.height();
-                for y in 0..self.height() {
-                    for x in 0..self.width() {
-                        let x = x + tx;
-                        let y = y + ty;
-                        let rl = risk_levels[y][x] + add;
-                        let rl = if rl > 9 { rl - 9 } else { rl };
-                        risk_levels[y][x] = rl;
-                    }
+    /// The risk map with the positions on `path` highlighted using ANSI escape codes.
+    fn render(&self, path: &[Pos]) -> String {
+        let on_path = path.iter().collect::<HashSet<_>>();
+        let mut out = String::new();
+        for y in 0..self.height() {
+            for x in 0..self.width() {
+                let pos = Pos::new(x, y);
+                let c = char::from_digit(self.risk_level(&pos) as u32, 36).unwrap_or('?');
+                if on_path.contains(&pos) {
+                    out.push_str(&format!("\x1b[1;31m{}\x1b[0m", c));
+                } else {
+                    out.push(c);
                 }
             }
+            out.push('\n');
         }
+        out
+    }
 
-        Cavern { risk_levels }
+    /// Total risk of a path, not counting the position it starts at.
+    fn path_risk(&self, path: &[Pos]) -> i32 {
+        path.iter().skip(1).map(|p| self.risk_level(p) as i32).sum()
+    }
+
+    /// The same scanned area repeated according to `tiling`, without expanding it in memory.
+    fn tiled(&self, tiling: Tiling) -> Result<Self> {
+        Cavern::new(self.risk_levels.clone(), tiling)
     }
 }
 
@@ -167,28 +288,29 @@ impl Pos {
     }
 }
 
-fn part1(input: &Input) -> i32 {
-    let path = input.lowest_risk_path();
-    path.iter()
-        .skip(1)
-        .map(|p| input.risk_level(p) as i32)
-        .sum()
+fn part1(input: &Input) -> Result<i32> {
+    let (start_pos, end_pos) = input.corners();
+    let path = input.lowest_risk_path(start_pos, end_pos, Movement::ORTHOGONAL)?;
+    Ok(input.path_risk(&path))
 }
 
-fn part2(input: &Input) -> i32 {
-    let input = input.expand(5);
-    let path = input.lowest_risk_path();
-    path.iter()
-        .skip(1)
-        .map(|p| input.risk_level(p) as i32)
-        .sum()
+fn part2(input: &Input) -> Result<i32> {
+    let input = input.tiled(Tiling::square(5)?)?;
+    let (start_pos, end_pos) = input.corners();
+    let path = input.lowest_risk_path(start_pos, end_pos, Movement::ORTHOGONAL)?;
+    Ok(input.path_risk(&path))
 }
 
 fn main() -> Result<()> {
     measure(|| {
         let input = input()?;
-        println!("Part1: {}", part1(&input));
-        println!("Part2: {}", part2(&input));
+        if cfg!(feature = "print") || env::args().any(|arg| arg == "--render") {
+            let (start_pos, end_pos) = input.corners();
+            let path = input.lowest_risk_path(start_pos, end_pos, Movement::ORTHOGONAL)?;
+            print!("{}", input.render(&path));
+        }
+        println!("Part1: {}", part1(&input)?);
+        println!("Part2: {}", part2(&input)?);
         Ok(())
     })
 }
@@ -199,12 +321,15 @@ fn read_input<R: Read>(reader: BufReader<R>) -> Result<Input> {
     for line in reader.lines() {
         let mut row = vec![];
         for c in line?.chars() {
-            row.push(c as u8 - b'0');
+            row.push(
+                c.to_digit(10)
+                    .with_context(|| format!("Bad risk level {:?}", c))? as u8,
+            );
         }
         risk_levels.push(row);
     }
 
-    Ok(Cavern { risk_levels })
+    Cavern::new(risk_levels, Tiling::NONE)
 }
 
 fn input() -> Result<Input> {
@@ -244,13 +369,96 @@ mod tests {
 
     #[test]
     fn test_part1() -> Result<()> {
-        assert_eq!(part1(&as_input(INPUT)?), 40);
+        assert_eq!(part1(&as_input(INPUT)?)?, 40);
         Ok(())
     }
 
     #[test]
     fn test_part2() -> Result<()> {
-        assert_eq!(part2(&as_input(INPUT)?), 315);
+        assert_eq!(part2(&as_input(INPUT)?)?, 315);
+        Ok(())
+    }
+
+    #[test]
+    fn test_tiling() -> Result<()> {
+        let input = as_input(INPUT)?;
+        let tiled = input.tiled(Tiling::square(5)?)?;
+        assert_eq!((tiled.width(), tiled.height()), (50, 50));
+        assert_eq!(tiled.risk_level(&Pos::new(10, 0)), 2);
+        assert_eq!(tiled.risk_level(&Pos::new(49, 49)), 9);
+        assert_eq!(tiled.risk_level(&Pos::new(9, 49)), 5);
+
+        let input = as_input("\n12\n34")?;
+        let tiled = input.tiled(Tiling::new(3, 1, 4, 1, 5)?)?;
+        assert_eq!((tiled.width(), tiled.height()), (6, 2));
+        // Raised by 4 each tile and wrapping around after 5, 1 -> 5 -> 4
+        assert_eq!(tiled.risk_level(&Pos::new(0, 0)), 1);
+        assert_eq!(tiled.risk_level(&Pos::new(2, 0)), 5);
+        assert_eq!(tiled.risk_level(&Pos::new(4, 0)), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_tiling_errors() -> Result<()> {
+        assert!(Tiling::new(0, 1, 1, 1, 9).is_err());
+        assert!(Tiling::new(1, 1, 1, 5, 4).is_err());
+        assert!(Tiling::new(1, 1, 9, 1, 9).is_err());
+        assert!(Tiling::new(2, 2, 0, 3, 3).is_ok());
+
+        // Risk levels of the scanned area have to fit the tiling
+        let input = as_input(INPUT)?;
+        assert!(input.tiled(Tiling::new(2, 2, 1, 1, 5)?).is_err());
+        assert!(input.tiled(Tiling::new(2, 2, 1, 2, 9)?).is_err());
+        assert!(as_input("\n1203\n1111").is_err());
+        assert!(as_input("\n12\n1").is_err());
+        assert!(as_input("\n1x\n11").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_movement() -> Result<()> {
+        let input = as_input(INPUT)?;
+        let diagonal = Movement { diagonal: true };
+
+        let path = input.lowest_risk_path(Pos::new(9, 9), Pos::new(0, 0), Movement::ORTHOGONAL)?;
+        assert_eq!(path.first(), Some(&Pos::new(9, 9)));
+        assert_eq!(path.last(), Some(&Pos::new(0, 0)));
+        assert_eq!(path.len(), 19);
+
+        let path = input.lowest_risk_path(Pos::new(0, 0), Pos::new(9, 9), diagonal)?;
+        assert!(input.path_risk(&path) < 40);
+        assert!(path
+            .windows(2)
+            .all(|w| w[0].x.abs_diff(w[1].x) <= 1 && w[0].y.abs_diff(w[1].y) <= 1));
+
+        let path = input.lowest_risk_path(Pos::new(3, 3), Pos::new(3, 3), diagonal)?;
+        assert_eq!(path, vec![Pos::new(3, 3)]);
+
+        // Only the tiled cavern reaches past the scanned area
+        assert!(input
+            .lowest_risk_path(Pos::new(0, 0), Pos::new(10, 9), diagonal)
+            .is_err());
+        assert!(input
+            .lowest_risk_path(Pos::new(0, 10), Pos::new(0, 0), diagonal)
+            .is_err());
+        let tiled = input.tiled(Tiling::new(2, 1, 1, 1, 9)?)?;
+        let path = tiled.lowest_risk_path(Pos::new(0, 0), Pos::new(19, 9), diagonal)?;
+        assert_eq!(path.last(), Some(&Pos::new(19, 9)));
+        assert!(tiled
+            .lowest_risk_path(Pos::new(0, 0), Pos::new(20, 9), diagonal)
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_render() -> Result<()> {
+        let input = as_input("\n19\n11")?;
+        let (start_pos, end_pos) = input.corners();
+        let path = input.lowest_risk_path(start_pos, end_pos, Movement::ORTHOGONAL)?;
+        assert_eq!(
+            input.render(&path),
+            "\x1b[1;31m1\x1b[0m9\n\x1b[1;31m1\x1b[0m\x1b[1;31m1\x1b[0m\n"
+        );
         Ok(())
     }
 }
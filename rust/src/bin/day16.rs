@@ -1,22 +1,72 @@
 use std::env;
+use std::fmt;
 use std::fs::File;
 use std::io::prelude::*;
 use std::io::BufReader;
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use bitvec::prelude::*;
+use num_bigint::BigUint;
 
-use utils::measure;
+use utils::{flag, measure};
 
-type Input = Vec<u8>;
+type Input = PacketEvents<HexBits<BufReader<File>>>;
+
+#[derive(Debug, Eq, PartialEq)]
+enum DecodeError {
+    UnexpectedEnd {
+        offset: usize,
+    },
+    UnknownTypeId {
+        offset: usize,
+        type_id: u8,
+    },
+    /// Sub-packets not taking up exactly the total length given by their operator.
+    LengthMismatch {
+        offset: usize,
+        expected: usize,
+    },
+    NonZeroPadding {
+        offset: usize,
+    },
+    InvalidHex {
+        column: usize,
+        found: char,
+    },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use DecodeError::*;
+        match self {
+            UnexpectedEnd { offset } => {
+                write!(f, "unexpected end of transmission at bit {}", offset)
+            }
+            UnknownTypeId { offset, type_id } => {
+                write!(f, "unknown type id {} at bit {}", type_id, offset)
+            }
+            LengthMismatch { offset, expected } => write!(
+                f,
+                "sub-packets of the operator at bit {} don't add up to {} bits",
+                offset, expected
+            ),
+            NonZeroPadding { offset } => write!(f, "non-zero padding at bit {}", offset),
+            InvalidHex { column, found } => {
+                write!(f, "invalid hex character '{}' at column {}", found, column)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
 
 #[derive(Debug, Eq, PartialEq)]
 enum PacketType {
-    Literal(u64),
+    Literal(BigUint),
     Operator(Operator, Vec<Packet>),
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
 enum Operator {
     Sum,
     Product,
@@ -28,9 +78,9 @@ enum Operator {
 }
 
 impl Operator {
-    fn from_type_id(type_id: u8) -> Self {
+    fn from_type_id(type_id: u8) -> Option<Self> {
         use Operator::*;
-        match type_id {
+        Some(match type_id {
             0 => Sum,
             1 => Product,
             2 => Minimum,
@@ -38,11 +88,104 @@ impl Operator {
             5 => GreaterThan,
             6 => LessThan,
             7 => EqualTo,
-            _ => unreachable!(),
+            _ => return None,
+        })
+    }
+
+    /// Symbol used for the operator in expressions.
+    fn symbol(&self) -> &'static str {
+        use Operator::*;
+        match self {
+            Sum => "+",
+            Product => "*",
+            Minimum => "min",
+            Maximum => "max",
+            GreaterThan => ">",
+            LessThan => "<",
+            EqualTo => "==",
+        }
+    }
+
+    fn apply(&self, mut values: impl Iterator<Item = BigUint>) -> BigUint {
+        use Operator::*;
+        match self {
+            Sum => values.sum(),
+            Product => values.product(),
+            Minimum => values.min().unwrap_or_default(),
+            Maximum => values.max().unwrap_or_default(),
+            GreaterThan => BigUint::from((values.next() > values.next()) as u8),
+            LessThan => BigUint::from((values.next() < values.next()) as u8),
+            EqualTo => BigUint::from((values.next() == values.next()) as u8),
+        }
+    }
+
+    /// Adds `value` to the operands collected so far, folding it into the previous one when the
+    /// operator allows it so at most two operands are ever kept.
+    fn push_operand(&self, operands: &mut Vec<BigUint>, value: BigUint) {
+        use Operator::*;
+        match (self, operands.pop()) {
+            (Sum, Some(acc)) => operands.push(acc + value),
+            (Product, Some(acc)) => operands.push(acc * value),
+            (Minimum, Some(acc)) => operands.push(acc.min(value)),
+            (Maximum, Some(acc)) => operands.push(acc.max(value)),
+            (_, acc) => {
+                operands.extend(acc);
+                operands.push(value);
+            }
+        }
+    }
+
+    fn type_id(&self) -> u8 {
+        use Operator::*;
+        match self {
+            Sum => 0,
+            Product => 1,
+            Minimum => 2,
+            Maximum => 3,
+            GreaterThan => 5,
+            LessThan => 6,
+            EqualTo => 7,
         }
     }
 }
 
+/// How an operator packet tells the length of its sub-packets, length type id 0 and 1.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum LengthType {
+    TotalLength,
+    SubPacketCount,
+}
+
+/// Reads `n` bits starting at `start` as a number, `offset` being the position of `bits` in the
+/// whole transmission.
+fn read_bits(
+    bits: &BitSlice<Msb0, u8>,
+    start: usize,
+    n: usize,
+    offset: usize,
+) -> Result<usize, DecodeError> {
+    bits.get(start..(start + n))
+        .map(|b| b.load_be::<usize>())
+        .ok_or(DecodeError::UnexpectedEnd {
+            offset: offset + bits.len(),
+        })
+}
+
+fn push_bits(bits: &mut BitVec<Msb0, u8>, value: usize, n: usize) {
+    for i in (0..n).rev() {
+        bits.push((value >> i) & 1 == 1);
+    }
+}
+
+/// Where a packet and its sub-packets were found in a transmission, in bits.
+#[derive(Debug)]
+struct Layout {
+    offset: usize,
+    len: usize,
+    length_type: Option<LengthType>,
+    sub_packets: Vec<Layout>,
+}
+
 #[derive(Debug, Eq, PartialEq)]
 struct Packet {
     version: u8,
@@ -50,82 +193,259 @@ struct Packet {
 }
 
 impl Packet {
-    fn decode_bytes(input: &Vec<u8>) -> Packet {
+    /// Decodes a whole transmission, which may only be followed by zeros padding it.
+    fn decode_bytes(input: &[u8]) -> Result<Packet, DecodeError> {
         let bits = input.view_bits::<Msb0>();
-        let (packet, _) = Packet::decode_bits(bits);
-        packet
+        let (packet, layout) = Packet::decode_bits(bits, 0)?;
+        if let Some(padding) = bits[layout.len..].iter().position(|b| *b) {
+            return Err(DecodeError::NonZeroPadding {
+                offset: layout.len + padding,
+            });
+        }
+        Ok(packet)
     }
 
-    fn decode_bits(bits: &BitSlice<Msb0, u8>) -> (Packet, usize) {
-        let version = bits[0..3].load_be::<u8>();
-        let type_id = bits[3..6].load_be::<u8>();
+    /// Decodes the packet at the start of `bits`, where `offset` is the position of `bits` in the
+    /// whole transmission.
+    fn decode_bits(
+        bits: &BitSlice<Msb0, u8>,
+        offset: usize,
+    ) -> Result<(Packet, Layout), DecodeError> {
+        let version = read_bits(bits, 0, 3, offset)? as u8;
+        let type_id = read_bits(bits, 3, 3, offset)? as u8;
         let mut consumed = 6;
+        let mut length_type = None;
+        let mut sub_packets = vec![];
 
         let packet_type = match type_id {
             4 => {
-                let mut b_idx = 6;
-                let mut lit_bits = bitvec![Msb0, u8;];
+                let mut nibbles = vec![];
                 loop {
+                    let group = read_bits(bits, consumed, 5, offset)?;
                     consumed += 5;
-                    let next = &bits[(b_idx + 1)..(b_idx + 5)];
-                    lit_bits.extend_from_bitslice(next);
+                    nibbles.push((group & 0xF) as u8);
 
-                    if !bits[b_idx] {
+                    if group & 0x10 == 0 {
                         break;
                     }
-                    b_idx += 5;
                 }
 
-                PacketType::Literal(lit_bits.load_be())
+                PacketType::Literal(BigUint::from_radix_be(&nibbles, 16).unwrap_or_default())
             }
             _ => {
+                let op = Operator::from_type_id(type_id).ok_or(DecodeError::UnknownTypeId {
+                    offset: offset + 3,
+                    type_id,
+                })?;
                 let mut packets = vec![];
 
-                let mut b_idx = 7;
-                let lenght_type_id = bits[6];
+                let lenght_type_id = read_bits(bits, 6, 1, offset)?;
                 consumed += 1;
 
-                if lenght_type_id == false {
-                    let subpackets_len = bits[(b_idx)..(b_idx + 15)].load_be::<usize>();
-                    b_idx += 15;
+                if lenght_type_id == 0 {
+                    length_type = Some(LengthType::TotalLength);
+                    let subpackets_len = read_bits(bits, consumed, 15, offset)?;
                     consumed += 15;
 
-                    let subpackets = &bits[(b_idx)..(b_idx + subpackets_len)];
+                    let subpackets = bits.get(consumed..(consumed + subpackets_len)).ok_or(
+                        DecodeError::UnexpectedEnd {
+                            offset: offset + bits.len(),
+                        },
+                    )?;
                     let mut s_idx = 0;
 
-                    while s_idx < subpackets_len - 1 {
-                        let (packet, consumed) = Packet::decode_bits(&subpackets[(s_idx)..]);
-                        s_idx += consumed;
+                    while s_idx < subpackets_len {
+                        let (packet, layout) =
+                            Packet::decode_bits(&subpackets[(s_idx)..], offset + consumed + s_idx)
+                                .map_err(|e| match e {
+                                    DecodeError::UnexpectedEnd { .. } => {
+                                        DecodeError::LengthMismatch {
+                                            offset,
+                                            expected: subpackets_len,
+                                        }
+                                    }
+                                    e => e,
+                                })?;
+                        s_idx += layout.len;
                         packets.push(packet);
+                        sub_packets.push(layout);
                     }
 
                     consumed += subpackets_len;
                 } else {
-                    let n_subpackets = bits[(b_idx)..(b_idx + 11)].load_be::<usize>();
-                    b_idx += 11;
+                    length_type = Some(LengthType::SubPacketCount);
+                    let n_subpackets = read_bits(bits, consumed, 11, offset)?;
                     consumed += 11;
 
                     for _ in 0..n_subpackets {
-                        let (packet, pconsumed) = Packet::decode_bits(&bits[(b_idx)..]);
-                        b_idx += pconsumed;
-                        consumed += pconsumed;
+                        let (packet, layout) =
+                            Packet::decode_bits(&bits[consumed..], offset + consumed)?;
+                        consumed += layout.len;
                         packets.push(packet);
+                        sub_packets.push(layout);
                     }
                 }
 
-                PacketType::Operator(Operator::from_type_id(type_id), packets)
+                PacketType::Operator(op, packets)
             }
         };
 
-        (
+        Ok((
             Packet {
                 version,
                 packet_type,
             },
-            consumed,
-        )
+            Layout {
+                offset,
+                len: consumed,
+                length_type,
+                sub_packets,
+            },
+        ))
     }
 
+    /// Appends the packet in BITS binary. Operators use `length_type` if given, otherwise the
+    /// sub-packet count as it's shorter, falling back to the total length if there are too many
+    /// sub-packets to count.
+    fn encode_bits(
+        &self,
+        bits: &mut BitVec<Msb0, u8>,
+        length_type: Option<LengthType>,
+    ) -> Result<()> {
+        push_bits(bits, self.version as usize, 3);
+
+        match &self.packet_type {
+            PacketType::Literal(value) => {
+                push_bits(bits, 4, 3);
+                let nibbles = value.to_radix_be(16);
+                for (i, &nibble) in nibbles.iter().enumerate() {
+                    bits.push(i + 1 < nibbles.len());
+                    push_bits(bits, nibble as usize, 4);
+                }
+            }
+            PacketType::Operator(op, packets) => {
+                push_bits(bits, op.type_id() as usize, 3);
+
+                let mut subpackets = bitvec![Msb0, u8;];
+                for packet in packets {
+                    packet.encode_bits(&mut subpackets, length_type)?;
+                }
+
+                let fits_count = packets.len() < 1 << 11;
+                let fits_length = subpackets.len() < 1 << 15;
+                match length_type {
+                    Some(LengthType::SubPacketCount) | None if fits_count => {
+                        bits.push(true);
+                        push_bits(bits, packets.len(), 11);
+                    }
+                    Some(LengthType::TotalLength) | None if fits_length => {
+                        bits.push(false);
+                        push_bits(bits, subpackets.len(), 15);
+                    }
+                    _ => {
+                        return Err(anyhow!(
+                            "{} sub-packets of {} bits in total don't fit",
+                            packets.len(),
+                            subpackets.len()
+                        ))
+                    }
+                }
+                bits.extend_from_bitslice(&subpackets);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The packet in BITS binary, padded with zeros to whole bytes.
+    fn encode_bytes(&self, length_type: Option<LengthType>) -> Result<Vec<u8>> {
+        let mut bits = bitvec![Msb0, u8;];
+        self.encode_bits(&mut bits, length_type)?;
+        while bits.len() % 8 != 0 {
+            bits.push(false);
+        }
+        Ok(bits.into_vec())
+    }
+
+    fn encode_hex(&self, length_type: Option<LengthType>) -> Result<String> {
+        Ok(self
+            .encode_bytes(length_type)?
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect())
+    }
+
+    /// The packet as an S-expression, like `(+ 1 (* 2 3))`.
+    fn to_sexpr(&self) -> String {
+        match &self.packet_type {
+            PacketType::Literal(value) => value.to_string(),
+            PacketType::Operator(op, packets) => {
+                let mut sexpr = format!("({}", op.symbol());
+                for p in packets {
+                    sexpr.push(' ');
+                    sexpr.push_str(&p.to_sexpr());
+                }
+                sexpr.push(')');
+                sexpr
+            }
+        }
+    }
+
+    /// One line per packet with its bit offset, version, type id and value, sub-packets indented
+    /// below their operator.
+    fn disassemble(&self, layout: &Layout) -> String {
+        let mut lines = vec![];
+        self.disassemble_into(layout, 0, &mut lines);
+        lines.concat()
+    }
+
+    /// Adds the lines of this packet to `lines` and returns its value, working it out from the
+    /// values of the sub-packets as they're disassembled.
+    fn disassemble_into(&self, layout: &Layout, depth: usize, lines: &mut Vec<String>) -> BigUint {
+        let line = lines.len();
+        lines.push(String::new());
+
+        let value = match &self.packet_type {
+            PacketType::Literal(value) => value.clone(),
+            PacketType::Operator(op, packets) => op.apply(
+                packets
+                    .iter()
+                    .zip(&layout.sub_packets)
+                    .map(|(packet, layout)| packet.disassemble_into(layout, depth + 1, lines))
+                    .collect::<Vec<_>>()
+                    .into_iter(),
+            ),
+        };
+
+        let (type_id, description) = match (&self.packet_type, layout.length_type) {
+            (PacketType::Literal(_), _) => (4, "literal".to_owned()),
+            (PacketType::Operator(op, packets), length_type) => {
+                let length = match length_type {
+                    Some(LengthType::TotalLength) => format!(
+                        "length type 0, {} bits",
+                        layout.sub_packets.iter().map(|l| l.len).sum::<usize>()
+                    ),
+                    _ => format!("length type 1, {} sub-packets", packets.len()),
+                };
+                (
+                    op.type_id(),
+                    format!("{:?} ({}), {}", op, op.symbol(), length),
+                )
+            }
+        };
+        lines[line] = format!(
+            "{:>6}  {}v{} t{} {} = {}\n",
+            layout.offset,
+            "  ".repeat(depth),
+            self.version,
+            type_id,
+            description,
+            value
+        );
+        value
+    }
+
+    #[cfg(test)]
     fn sum_version(&self) -> i32 {
         match &self.packet_type {
             PacketType::Literal(_) => self.version as i32,
@@ -135,87 +455,649 @@ impl Packet {
         }
     }
 
-    fn calculate(&self) -> u64 {
-        use Operator::*;
+    #[cfg(test)]
+    fn calculate(&self) -> BigUint {
         match &self.packet_type {
-            PacketType::Literal(value) => *value,
-            PacketType::Operator(op, packets) => {
-                let mut values = packets.iter().map(|p| p.calculate());
-                match op {
-                    Sum => values.sum(),
-                    Product => values.product(),
-                    Minimum => values.min().unwrap_or(0),
-                    Maximum => values.max().unwrap_or(0),
-                    GreaterThan => (values.next() > values.next()) as u64,
-                    LessThan => (values.next() < values.next()) as u64,
-                    EqualTo => (values.next() == values.next()) as u64,
+            PacketType::Literal(value) => value.clone(),
+            PacketType::Operator(op, packets) => op.apply(packets.iter().map(|p| p.calculate())),
+        }
+    }
+}
+
+/// Bits of a hex transmission, read from the stream a nibble at a time. The transmission ends
+/// with the stream or the first line break.
+struct HexBits<R: BufRead> {
+    bytes: std::io::Bytes<R>,
+    column: usize,
+    nibble: u8,
+    nibble_bits: u8,
+}
+
+impl<R: BufRead> HexBits<R> {
+    fn new(reader: R) -> Self {
+        HexBits {
+            bytes: reader.bytes(),
+            column: 0,
+            nibble: 0,
+            nibble_bits: 0,
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for HexBits<R> {
+    type Item = Result<bool>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.nibble_bits == 0 {
+            let c = match self.bytes.next()? {
+                Ok(b'\n') | Ok(b'\r') => return None,
+                Ok(b) => b as char,
+                Err(e) => return Some(Err(e.into())),
+            };
+            self.column += 1;
+            self.nibble = match hex2bin(self.column, c) {
+                Ok(nibble) => nibble,
+                Err(e) => return Some(Err(e.into())),
+            };
+            self.nibble_bits = 4;
+        }
+
+        self.nibble_bits -= 1;
+        Some(Ok((self.nibble >> self.nibble_bits) & 1 == 1))
+    }
+}
+
+/// What the streaming decoder found at a bit offset of the transmission.
+#[derive(Debug, Eq, PartialEq)]
+enum Event {
+    Start {
+        offset: usize,
+        version: u8,
+        operator: Operator,
+        length_type: LengthType,
+    },
+    Literal {
+        offset: usize,
+        version: u8,
+        value: BigUint,
+    },
+    /// The last sub-packet of the operator ended just before `offset`.
+    End { offset: usize, operator: Operator },
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::Start {
+                offset,
+                version,
+                operator,
+                length_type,
+            } => write!(
+                f,
+                "{:>6}  v{} start {:?}, length type {}",
+                offset, version, operator, *length_type as u8
+            ),
+            Event::Literal {
+                offset,
+                version,
+                value,
+            } => write!(f, "{:>6}  v{} literal {}", offset, version, value),
+            Event::End { offset, operator } => write!(f, "{:>6}  end {:?}", offset, operator),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Remaining {
+    /// Sub-packets must end exactly at bit `end`, `len` bits after they started.
+    Bits {
+        end: usize,
+        len: usize,
+    },
+    Packets(usize),
+}
+
+/// An operator whose sub-packets are still being decoded.
+#[derive(Debug)]
+struct Frame {
+    offset: usize,
+    operator: Operator,
+    remaining: Remaining,
+}
+
+/// Decodes a transmission into events as its bits come in, only keeping the operators that
+/// enclose the current bit, so memory is bounded by the nesting depth rather than the length.
+struct PacketEvents<I: Iterator<Item = Result<bool>>> {
+    bits: I,
+    offset: usize,
+    stack: Vec<Frame>,
+    decoded: bool,
+    fused: bool,
+}
+
+impl<I: Iterator<Item = Result<bool>>> PacketEvents<I> {
+    fn new(bits: I) -> Self {
+        PacketEvents {
+            bits,
+            offset: 0,
+            stack: vec![],
+            decoded: false,
+            fused: false,
+        }
+    }
+
+    /// Reads `n` bits, which must not run past the end of any enclosing total length block.
+    fn take_bits(&mut self, n: usize) -> Result<usize> {
+        let limit = self
+            .stack
+            .iter()
+            .filter_map(|frame| match frame.remaining {
+                Remaining::Bits { end, len } => Some((end, frame.offset, len)),
+                Remaining::Packets(_) => None,
+            })
+            .min();
+        if let Some((end, offset, len)) = limit {
+            if self.offset + n > end {
+                return Err(DecodeError::LengthMismatch {
+                    offset,
+                    expected: len,
                 }
+                .into());
             }
         }
+
+        let mut value = 0;
+        for _ in 0..n {
+            let bit = self.bits.next().ok_or(DecodeError::UnexpectedEnd {
+                offset: self.offset,
+            })??;
+            value = (value << 1) | bit as usize;
+            self.offset += 1;
+        }
+        Ok(value)
+    }
+
+    /// Counts a finished packet against the operator containing it.
+    fn finish_packet(&mut self) {
+        match self.stack.last_mut() {
+            Some(Frame {
+                remaining: Remaining::Packets(n),
+                ..
+            }) => *n -= 1,
+            Some(_) => {}
+            None => self.decoded = true,
+        }
+    }
+
+    fn close_operator(&mut self) -> Option<Result<Event>> {
+        let frame = self.stack.last()?;
+        match frame.remaining {
+            Remaining::Bits { end, .. } if self.offset < end => return None,
+            Remaining::Packets(n) if n > 0 => return None,
+            _ => {}
+        }
+
+        let frame = self.stack.pop()?;
+        self.finish_packet();
+        Some(Ok(Event::End {
+            offset: self.offset,
+            operator: frame.operator,
+        }))
+    }
+
+    fn check_padding(&mut self) -> Result<()> {
+        for bit in &mut self.bits {
+            if bit? {
+                return Err(DecodeError::NonZeroPadding {
+                    offset: self.offset,
+                }
+                .into());
+            }
+            self.offset += 1;
+        }
+        Ok(())
+    }
+
+    fn decode_packet(&mut self) -> Result<Event> {
+        let offset = self.offset;
+        let version = self.take_bits(3)? as u8;
+        let type_id = self.take_bits(3)? as u8;
+
+        if type_id == 4 {
+            let mut value = BigUint::default();
+            loop {
+                let group = self.take_bits(5)?;
+                value = (value << 4_u32) | BigUint::from(group & 0xF);
+                if group & 0x10 == 0 {
+                    break;
+                }
+            }
+            self.finish_packet();
+            return Ok(Event::Literal {
+                offset,
+                version,
+                value,
+            });
+        }
+
+        let operator = Operator::from_type_id(type_id).ok_or(DecodeError::UnknownTypeId {
+            offset: offset + 3,
+            type_id,
+        })?;
+        let (length_type, remaining) = if self.take_bits(1)? == 0 {
+            let len = self.take_bits(15)?;
+            let end = self.offset + len;
+            (LengthType::TotalLength, Remaining::Bits { end, len })
+        } else {
+            let n = self.take_bits(11)?;
+            (LengthType::SubPacketCount, Remaining::Packets(n))
+        };
+        self.stack.push(Frame {
+            offset,
+            operator,
+            remaining,
+        });
+
+        Ok(Event::Start {
+            offset,
+            version,
+            operator,
+            length_type,
+        })
     }
 }
 
-fn part1(input: &Input) -> i32 {
-    Packet::decode_bytes(input).sum_version()
+impl<I: Iterator<Item = Result<bool>>> Iterator for PacketEvents<I> {
+    type Item = Result<Event>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.fused {
+            return None;
+        }
+
+        let event = if let Some(end) = self.close_operator() {
+            Some(end)
+        } else if self.decoded {
+            self.fused = true;
+            self.check_padding().err().map(Err)
+        } else {
+            Some(self.decode_packet())
+        };
+
+        if let Some(Err(_)) = event {
+            self.fused = true;
+        }
+        event
+    }
 }
 
-fn part2(input: &Input) -> u64 {
-    Packet::decode_bytes(input).calculate()
+/// Sums the versions and calculates the value of a transmission in a single pass over its
+/// events, keeping at most two operands per open operator.
+fn evaluate_events(events: impl Iterator<Item = Result<Event>>) -> Result<(i32, BigUint)> {
+    let mut version_sum = 0;
+    let mut stack: Vec<(Operator, Vec<BigUint>)> = vec![];
+    let mut result = None;
+
+    for event in events {
+        let value = match event? {
+            Event::Start {
+                version, operator, ..
+            } => {
+                version_sum += version as i32;
+                stack.push((operator, vec![]));
+                continue;
+            }
+            Event::Literal { version, value, .. } => {
+                version_sum += version as i32;
+                value
+            }
+            Event::End { .. } => {
+                let (operator, operands) = stack
+                    .pop()
+                    .ok_or_else(|| anyhow!("End of an operator that never started"))?;
+                operator.apply(operands.into_iter())
+            }
+        };
+
+        match stack.last_mut() {
+            Some((operator, operands)) => operator.push_operand(operands, value),
+            None => result = Some(value),
+        }
+    }
+
+    Ok((version_sum, result.context("No packet in transmission")?))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(BigUint),
+    Ident(String),
+    Plus,
+    Star,
+    Greater,
+    Less,
+    EqualEqual,
+    LParen,
+    RParen,
+    Comma,
+}
+
+/// Splits source text into tokens, each along with the column it starts at.
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>> {
+    let chars = source.chars().collect::<Vec<_>>();
+    let mut tokens = vec![];
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let column = i + 1;
+        let token = match c {
+            _ if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '0'..='9' => {
+                let digits = chars[i..]
+                    .iter()
+                    .take_while(|c| c.is_ascii_digit())
+                    .collect::<String>();
+                i += digits.len();
+                tokens.push((
+                    Token::Number(
+                        digits
+                            .parse()
+                            .with_context(|| format!("Invalid number at {}", column))?,
+                    ),
+                    column,
+                ));
+                continue;
+            }
+            'a'..='z' => {
+                let ident = chars[i..]
+                    .iter()
+                    .take_while(|c| c.is_ascii_lowercase())
+                    .collect::<String>();
+                i += ident.len();
+                tokens.push((Token::Ident(ident), column));
+                continue;
+            }
+            '=' if chars.get(i + 1) == Some(&'=') => {
+                i += 1;
+                Token::EqualEqual
+            }
+            '+' => Token::Plus,
+            '*' => Token::Star,
+            '>' => Token::Greater,
+            '<' => Token::Less,
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            ',' => Token::Comma,
+            _ => return Err(anyhow!("Unexpected '{}' at {}", c, column)),
+        };
+        tokens.push((token, column));
+        i += 1;
+    }
+
+    Ok(tokens)
+}
+
+/// Expression parsed from source text, to be compiled into packets.
+#[derive(Debug, PartialEq)]
+enum Expr {
+    Literal(BigUint),
+    Apply(Operator, Vec<Expr>),
+}
+
+/// Recursive descent parser for the grammar below, where comparisons bind loosest and can't be
+/// chained as they only take two operands.
+///
+/// ```text
+/// expr    = sum [ ( ">" | "<" | "==" ) sum ]
+/// sum     = product { "+" product }
+/// product = atom { "*" atom }
+/// atom    = number | "(" expr ")" | ( "min" | "max" | "sum" | "product" ) "(" expr { "," expr } ")"
+/// ```
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    end: usize,
+}
+
+impl Parser {
+    fn parse(source: &str) -> Result<Expr> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            pos: 0,
+            end: source.chars().count() + 1,
+        };
+        let expr = parser.expr()?;
+        if let Some((token, column)) = parser.tokens.get(parser.pos) {
+            return Err(anyhow!("Unexpected {:?} at {}", token, column));
+        }
+        Ok(expr)
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(t, _)| t)
+    }
+
+    fn column(&self) -> usize {
+        self.tokens.get(self.pos).map_or(self.end, |&(_, c)| c)
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<()> {
+        match self.peek() {
+            Some(t) if *t == expected => {
+                self.pos += 1;
+                Ok(())
+            }
+            Some(t) => Err(anyhow!(
+                "Expected {:?} at {}, found {:?}",
+                expected,
+                self.column(),
+                t
+            )),
+            None => Err(anyhow!("Expected {:?} at {}", expected, self.column())),
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr> {
+        let lhs = self.sum()?;
+        let op = match self.peek() {
+            Some(Token::Greater) => Operator::GreaterThan,
+            Some(Token::Less) => Operator::LessThan,
+            Some(Token::EqualEqual) => Operator::EqualTo,
+            _ => return Ok(lhs),
+        };
+        self.pos += 1;
+        let rhs = self.sum()?;
+        Ok(Expr::Apply(op, vec![lhs, rhs]))
+    }
+
+    fn sum(&mut self) -> Result<Expr> {
+        let mut terms = vec![self.product()?];
+        while self.peek() == Some(&Token::Plus) {
+            self.pos += 1;
+            terms.push(self.product()?);
+        }
+        Ok(match terms.len() {
+            1 => terms.remove(0),
+            _ => Expr::Apply(Operator::Sum, terms),
+        })
+    }
+
+    fn product(&mut self) -> Result<Expr> {
+        let mut factors = vec![self.atom()?];
+        while self.peek() == Some(&Token::Star) {
+            self.pos += 1;
+            factors.push(self.atom()?);
+        }
+        Ok(match factors.len() {
+            1 => factors.remove(0),
+            _ => Expr::Apply(Operator::Product, factors),
+        })
+    }
+
+    fn atom(&mut self) -> Result<Expr> {
+        let column = self.column();
+        match self.peek().cloned() {
+            Some(Token::Number(n)) => {
+                self.pos += 1;
+                Ok(Expr::Literal(n))
+            }
+            Some(Token::LParen) => {
+                self.pos += 1;
+                let expr = self.expr()?;
+                self.expect(Token::RParen)?;
+                Ok(expr)
+            }
+            Some(Token::Ident(name)) => {
+                let op = match name.as_str() {
+                    "min" => Operator::Minimum,
+                    "max" => Operator::Maximum,
+                    "sum" => Operator::Sum,
+                    "product" => Operator::Product,
+                    _ => return Err(anyhow!("Unknown function {} at {}", name, column)),
+                };
+                self.pos += 1;
+                self.expect(Token::LParen)?;
+                let mut args = vec![self.expr()?];
+                while self.peek() == Some(&Token::Comma) {
+                    self.pos += 1;
+                    args.push(self.expr()?);
+                }
+                self.expect(Token::RParen)?;
+                Ok(Expr::Apply(op, args))
+            }
+            Some(t) => Err(anyhow!("Unexpected {:?} at {}", t, column)),
+            None => Err(anyhow!("Unexpected end of expression at {}", column)),
+        }
+    }
+}
+
+impl Expr {
+    fn to_packet(&self) -> Packet {
+        let packet_type = match self {
+            Expr::Literal(value) => PacketType::Literal(value.clone()),
+            Expr::Apply(op, args) => {
+                PacketType::Operator(*op, args.iter().map(|a| a.to_packet()).collect())
+            }
+        };
+        Packet {
+            version: 0,
+            packet_type,
+        }
+    }
+}
+
+/// Compiles an infix expression into a BITS transmission in hex.
+fn compile(source: &str) -> Result<String> {
+    Parser::parse(source)?.to_packet().encode_hex(None)
+}
+
+/// Length type for `--encode`, by its id or "auto" for whichever is shorter.
+fn length_type(id: &str) -> Result<Option<LengthType>> {
+    match id {
+        "0" => Ok(Some(LengthType::TotalLength)),
+        "1" => Ok(Some(LengthType::SubPacketCount)),
+        "auto" => Ok(None),
+        _ => Err(anyhow!("Unknown length type {}, expected 0, 1 or auto", id)),
+    }
 }
 
 fn main() -> Result<()> {
     measure(|| {
-        let input = input()?;
-        println!("Part1: {}", part1(&input));
-        println!("Part2: {}", part2(&input));
+        // Compiling an expression doesn't need a transmission to read.
+        if let Some(source) = flag("--compile") {
+            println!("Compiled: {}", compile(&source)?);
+            return Ok(());
+        }
+
+        // Both take the whole transmission, unlike the parts which only keep what's open.
+        if cfg!(feature = "print") || env::args().any(|arg| arg == "--disassemble") {
+            let transmission = transmission()?;
+            let (packet, layout) = Packet::decode_bits(transmission.view_bits::<Msb0>(), 0)?;
+            print!("{}", packet.disassemble(&layout));
+            println!("{}", packet.to_sexpr());
+        }
+
+        let trace = env::args().any(|arg| arg == "--events");
+        let events = input()?.inspect(|event| match event {
+            Ok(event) if trace => println!("{}", event),
+            _ => {}
+        });
+        let (part1, part2) = evaluate_events(events)?;
+        println!("Part1: {}", part1);
+        println!("Part2: {}", part2);
+
+        if let Some(id) = flag("--encode") {
+            let packet = Packet::decode_bytes(&transmission()?)?;
+            println!("Encoded: {}", packet.encode_hex(length_type(&id)?)?);
+        }
         Ok(())
     })
 }
 
-fn read_input<R: Read>(reader: BufReader<R>) -> Result<Input> {
+fn hex2bin(column: usize, c: char) -> Result<u8, DecodeError> {
     const HEX_2_BIN: [char; 16] = [
         '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', 'A', 'B', 'C', 'D', 'E', 'F',
     ];
-    fn hex2bin(c: char) -> u8 {
-        HEX_2_BIN
-            .iter()
-            .enumerate()
-            .find(|&(_, hc)| hc == &c)
-            .map(|(i, _)| i as u8)
-            .unwrap_or(0)
-    }
+    HEX_2_BIN
+        .iter()
+        .position(|&hc| hc == c.to_ascii_uppercase())
+        .map(|i| i as u8)
+        .ok_or(DecodeError::InvalidHex { column, found: c })
+}
 
-    let chars = reader
+/// Decodes the transmission as it's read, see `PacketEvents`.
+fn read_input<R: Read>(reader: BufReader<R>) -> PacketEvents<HexBits<BufReader<R>>> {
+    PacketEvents::new(HexBits::new(reader))
+}
+
+/// Reads the whole transmission into bytes.
+fn read_transmission<R: Read>(reader: BufReader<R>) -> Result<Vec<u8>> {
+    let nibbles = reader
         .lines()
         .next()
         .context("No input")??
+        .trim()
         .chars()
-        .collect::<Vec<_>>();
-    let in_binary = chars
+        .enumerate()
+        .map(|(i, c)| hex2bin(i + 1, c))
+        .collect::<Result<Vec<_>, _>>()?;
+    let in_binary = nibbles
         .chunks(2)
         .map(|c| {
-            let nibble1 = hex2bin(c[0]);
-            let nibble2 = hex2bin(c[1]);
+            let nibble1 = c[0];
+            let nibble2 = c.get(1).copied().unwrap_or(0);
             (nibble1 << 4) | nibble2
         })
         .collect::<Vec<_>>();
     Ok(in_binary)
 }
 
-fn input() -> Result<Input> {
-    let path = env::args()
+fn path() -> Result<String> {
+    env::args()
         .skip(1)
         .next()
-        .with_context(|| format!("No input file given"))?;
-    read_input(BufReader::new(File::open(path)?))
+        .with_context(|| format!("No input file given"))
+}
+
+fn input() -> Result<Input> {
+    Ok(read_input(BufReader::new(File::open(path()?)?)))
+}
+
+fn transmission() -> Result<Vec<u8>> {
+    read_transmission(BufReader::new(File::open(path()?)?))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    fn as_input(s: &str) -> Result<Input> {
-        read_input(BufReader::new(
+    fn as_input(s: &str) -> Result<Vec<u8>> {
+        read_transmission(BufReader::new(
             s.split('\n')
                 .map(|s| s.trim())
                 .collect::<Vec<_>>()
@@ -227,15 +1109,15 @@ mod tests {
     #[test]
     fn test_decode_packet() -> Result<()> {
         assert_eq!(
-            Packet::decode_bytes(&as_input("D2FE28")?),
+            Packet::decode_bytes(&as_input("D2FE28")?)?,
             Packet {
                 version: 6,
-                packet_type: PacketType::Literal(2021)
+                packet_type: PacketType::Literal(BigUint::from(2021_u32))
             }
         );
 
         assert_eq!(
-            Packet::decode_bytes(&as_input("38006F45291200")?),
+            Packet::decode_bytes(&as_input("38006F45291200")?)?,
             Packet {
                 version: 1,
                 packet_type: PacketType::Operator(
@@ -243,11 +1125,11 @@ mod tests {
                     vec![
                         Packet {
                             version: 6,
-                            packet_type: PacketType::Literal(10)
+                            packet_type: PacketType::Literal(BigUint::from(10_u32))
                         },
                         Packet {
                             version: 2,
-                            packet_type: PacketType::Literal(20)
+                            packet_type: PacketType::Literal(BigUint::from(20_u32))
                         }
                     ]
                 )
@@ -255,7 +1137,7 @@ mod tests {
         );
 
         assert_eq!(
-            Packet::decode_bytes(&as_input("EE00D40C823060")?),
+            Packet::decode_bytes(&as_input("EE00D40C823060")?)?,
             Packet {
                 version: 7,
                 packet_type: PacketType::Operator(
@@ -263,15 +1145,15 @@ mod tests {
                     vec![
                         Packet {
                             version: 2,
-                            packet_type: PacketType::Literal(1)
+                            packet_type: PacketType::Literal(BigUint::from(1_u32))
                         },
                         Packet {
                             version: 4,
-                            packet_type: PacketType::Literal(2)
+                            packet_type: PacketType::Literal(BigUint::from(2_u32))
                         },
                         Packet {
                             version: 1,
-                            packet_type: PacketType::Literal(3)
+                            packet_type: PacketType::Literal(BigUint::from(3_u32))
                         }
                     ]
                 )
@@ -281,25 +1163,393 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_decode_errors() -> Result<()> {
+        assert_eq!(
+            Packet::decode_bytes(&as_input("D2FE")?),
+            Err(DecodeError::UnexpectedEnd { offset: 16 })
+        );
+        assert_eq!(
+            Packet::decode_bytes(&as_input("D2FE29")?),
+            Err(DecodeError::NonZeroPadding { offset: 23 })
+        );
+        // Total length of 26 instead of 27 bits cuts the second literal short.
+        assert_eq!(
+            Packet::decode_bytes(&as_input("38006B45291200")?),
+            Err(DecodeError::LengthMismatch {
+                offset: 0,
+                expected: 26
+            })
+        );
+        assert_eq!(
+            as_input("D2FG28")
+                .unwrap_err()
+                .downcast_ref::<DecodeError>(),
+            Some(&DecodeError::InvalidHex {
+                column: 4,
+                found: 'G'
+            })
+        );
+        assert_eq!(as_input("d2fe28")?, as_input("D2FE28")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_big_literal() -> Result<()> {
+        let value = BigUint::from(u64::MAX) << 40_u32;
+        let packet = Packet {
+            version: 3,
+            packet_type: PacketType::Literal(value.clone()),
+        };
+        let hex = packet.encode_hex(None)?;
+        assert_eq!(Packet::decode_bytes(&as_input(&hex)?)?.calculate(), value);
+        Ok(())
+    }
+
+    fn stream(hex: &str) -> PacketEvents<HexBits<BufReader<&[u8]>>> {
+        read_input(BufReader::new(hex.as_bytes()))
+    }
+
     #[test]
     fn test_part1() -> Result<()> {
-        assert_eq!(part1(&as_input("8A004A801A8002F478")?), 16);
-        assert_eq!(part1(&as_input("620080001611562C8802118E34")?), 12);
-        assert_eq!(part1(&as_input("C0015000016115A2E0802F182340")?), 23);
-        assert_eq!(part1(&as_input("A0016C880162017C3686B18A3D4780")?), 31);
+        assert_eq!(evaluate_events(stream("8A004A801A8002F478"))?.0, 16);
+        assert_eq!(evaluate_events(stream("620080001611562C8802118E34"))?.0, 12);
+        assert_eq!(
+            evaluate_events(stream("C0015000016115A2E0802F182340"))?.0,
+            23
+        );
+        assert_eq!(
+            evaluate_events(stream("A0016C880162017C3686B18A3D4780"))?.0,
+            31
+        );
         Ok(())
     }
 
     #[test]
     fn test_part2() -> Result<()> {
-        assert_eq!(part2(&as_input("C200B40A82")?), 3);
-        assert_eq!(part2(&as_input("04005AC33890")?), 54);
-        assert_eq!(part2(&as_input("880086C3E88112")?), 7);
-        assert_eq!(part2(&as_input("CE00C43D881120")?), 9);
-        assert_eq!(part2(&as_input("D8005AC2A8F0")?), 1);
-        assert_eq!(part2(&as_input("F600BC2D8F")?), 0);
-        assert_eq!(part2(&as_input("9C005AC2F8F0")?), 0);
-        assert_eq!(part2(&as_input("9C0141080250320F1802104A08")?), 1);
+        assert_eq!(
+            evaluate_events(stream("C200B40A82"))?.1,
+            BigUint::from(3_u32)
+        );
+        assert_eq!(
+            evaluate_events(stream("04005AC33890"))?.1,
+            BigUint::from(54_u32)
+        );
+        assert_eq!(
+            evaluate_events(stream("880086C3E88112"))?.1,
+            BigUint::from(7_u32)
+        );
+        assert_eq!(
+            evaluate_events(stream("CE00C43D881120"))?.1,
+            BigUint::from(9_u32)
+        );
+        assert_eq!(
+            evaluate_events(stream("D8005AC2A8F0"))?.1,
+            BigUint::from(1_u32)
+        );
+        assert_eq!(
+            evaluate_events(stream("F600BC2D8F"))?.1,
+            BigUint::from(0_u32)
+        );
+        assert_eq!(
+            evaluate_events(stream("9C005AC2F8F0"))?.1,
+            BigUint::from(0_u32)
+        );
+        assert_eq!(
+            evaluate_events(stream("9C0141080250320F1802104A08"))?.1,
+            BigUint::from(1_u32)
+        );
+        Ok(())
+    }
+
+    /// Small linear congruential generator to build packets for round trips.
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next(&mut self, n: u64) -> u64 {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (self.0 >> 33) % n
+        }
+
+        fn packet(&mut self, depth: usize) -> Packet {
+            let version = self.next(8) as u8;
+            if depth == 0 || self.next(3) == 0 {
+                let value = match self.next(4) {
+                    0 => BigUint::from(self.next(16)),
+                    1 => BigUint::from(self.next(1 << 31)),
+                    2 => BigUint::from(self.next(u64::MAX) << self.next(32)),
+                    _ => BigUint::from(self.next(u64::MAX)) << self.next(100),
+                };
+                return Packet {
+                    version,
+                    packet_type: PacketType::Literal(value),
+                };
+            }
+
+            let type_id = [0, 1, 2, 3, 5, 6, 7][self.next(7) as usize];
+            let op = Operator::from_type_id(type_id).unwrap();
+            let n = if type_id >= 5 { 2 } else { 1 + self.next(4) };
+            let packets = (0..n).map(|_| self.packet(depth - 1)).collect();
+            Packet {
+                version,
+                packet_type: PacketType::Operator(op, packets),
+            }
+        }
+    }
+
+    #[test]
+    fn test_encode_packet() -> Result<()> {
+        for hex in ["D2FE28", "EE00D40C823060"] {
+            assert_eq!(
+                Packet::decode_bytes(&as_input(hex)?)?.encode_hex(None)?,
+                hex
+            );
+        }
+        let packet = Packet::decode_bytes(&as_input("38006F45291200")?)?;
+        assert_eq!(
+            packet.encode_hex(Some(LengthType::TotalLength))?,
+            "38006F45291200"
+        );
+
+        let packet = Packet {
+            version: 0,
+            packet_type: PacketType::Operator(
+                Operator::Sum,
+                (0..3000)
+                    .map(|i| Packet {
+                        version: 0,
+                        packet_type: PacketType::Literal(BigUint::from(i % 16_u32)),
+                    })
+                    .collect(),
+            ),
+        };
+        assert!(packet
+            .encode_bytes(Some(LengthType::SubPacketCount))
+            .is_err());
+        assert!(packet.encode_bytes(Some(LengthType::TotalLength)).is_err());
+
+        assert_eq!(length_type("0")?, Some(LengthType::TotalLength));
+        assert_eq!(length_type("auto")?, None);
+        assert!(length_type("2").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_encode_decode_round_trip() -> Result<()> {
+        let mut rng = Lcg(2021);
+        for _ in 0..200 {
+            let packet = rng.packet(4);
+            for length_type in [None, Some(LengthType::TotalLength)] {
+                let bytes = packet.encode_bytes(length_type)?;
+                assert_eq!(Packet::decode_bytes(&bytes)?, packet);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_packet_events() -> Result<()> {
+        assert_eq!(
+            stream("38006F45291200\n").collect::<Result<Vec<_>>>()?,
+            vec![
+                Event::Start {
+                    offset: 0,
+                    version: 1,
+                    operator: Operator::LessThan,
+                    length_type: LengthType::TotalLength
+                },
+                Event::Literal {
+                    offset: 22,
+                    version: 6,
+                    value: BigUint::from(10_u32)
+                },
+                Event::Literal {
+                    offset: 33,
+                    version: 2,
+                    value: BigUint::from(20_u32)
+                },
+                Event::End {
+                    offset: 49,
+                    operator: Operator::LessThan
+                },
+            ]
+        );
+
+        assert_eq!(
+            stream("38006F45291200")
+                .map(|event| Ok(event?.to_string()))
+                .collect::<Result<Vec<_>>>()?,
+            vec![
+                "     0  v1 start LessThan, length type 0",
+                "    22  v6 literal 10",
+                "    33  v2 literal 20",
+                "    49  end LessThan",
+            ]
+        );
+
+        for (hex, version_sum, value) in [
+            ("A0016C880162017C3686B18A3D4780", 31, 54),
+            ("9C0141080250320F1802104A08", 20, 1),
+            ("d2fe28", 6, 2021),
+        ] {
+            assert_eq!(
+                evaluate_events(stream(hex))?,
+                (version_sum, BigUint::from(value as u32))
+            );
+        }
+
+        let mut rng = Lcg(16);
+        for _ in 0..100 {
+            let packet = rng.packet(5);
+            let hex = packet.encode_hex(Some(LengthType::TotalLength))?;
+            assert_eq!(
+                evaluate_events(stream(&hex))?,
+                (packet.sum_version(), packet.calculate())
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_packet_events_errors() {
+        let error = |hex| {
+            evaluate_events(stream(hex))
+                .unwrap_err()
+                .downcast::<DecodeError>()
+                .ok()
+        };
+
+        assert_eq!(
+            error("D2FE"),
+            Some(DecodeError::UnexpectedEnd { offset: 16 })
+        );
+        assert_eq!(
+            error("D2FE29"),
+            Some(DecodeError::NonZeroPadding { offset: 23 })
+        );
+        assert_eq!(
+            error("38006B45291200"),
+            Some(DecodeError::LengthMismatch {
+                offset: 0,
+                expected: 26
+            })
+        );
+        assert_eq!(
+            error("D2FG28"),
+            Some(DecodeError::InvalidHex {
+                column: 4,
+                found: 'G'
+            })
+        );
+
+        let mut events = stream("D2FE");
+        assert!(events.next().unwrap().is_err());
+        assert!(events.next().is_none());
+    }
+
+    #[test]
+    fn test_compile() -> Result<()> {
+        let calculate = |source| -> Result<BigUint> {
+            Ok(Packet::decode_bytes(&as_input(&compile(source)?)?)?.calculate())
+        };
+
+        assert_eq!(calculate("min(3, 5*2) + (7 > 4)")?, BigUint::from(4_u64));
+        assert_eq!(calculate("1 + 2 * 3 + 4")?, BigUint::from(11_u64));
+        assert_eq!(calculate("(1 + 2) * 3 == 9")?, BigUint::from(1_u64));
+        assert_eq!(
+            calculate("max(1, 20, 3) < sum(4, 5) * product(2)")?,
+            BigUint::from(0_u64)
+        );
+        assert_eq!(calculate("12345678901")?, BigUint::from(12345678901_u64));
+        assert_eq!(
+            calculate("18446744073709551616 * 2")?,
+            BigUint::from(u64::MAX) * 2_u32 + 2_u32
+        );
+
+        assert_eq!(
+            Parser::parse("1 + 2 * 3")?,
+            Expr::Apply(
+                Operator::Sum,
+                vec![
+                    Expr::Literal(BigUint::from(1_u32)),
+                    Expr::Apply(
+                        Operator::Product,
+                        vec![
+                            Expr::Literal(BigUint::from(2_u32)),
+                            Expr::Literal(BigUint::from(3_u32))
+                        ]
+                    )
+                ]
+            )
+        );
+
+        for source in [
+            "",
+            "1 +",
+            "(1",
+            "1 > 2 > 3",
+            "min()",
+            "foo(1)",
+            "1 - 2",
+            "1 2",
+        ] {
+            assert!(compile(source).is_err(), "{}", source);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_disassemble() -> Result<()> {
+        let input = as_input("38006F45291200")?;
+        let (packet, layout) = Packet::decode_bits(input.view_bits::<Msb0>(), 0)?;
+        assert_eq!(
+            packet.disassemble(&layout),
+            [
+                "     0  v1 t6 LessThan (<), length type 0, 27 bits = 1",
+                "    22    v6 t4 literal = 10",
+                "    33    v2 t4 literal = 20",
+                "",
+            ]
+            .join("\n")
+        );
+
+        let input = as_input("EE00D40C823060")?;
+        let (packet, layout) = Packet::decode_bits(input.view_bits::<Msb0>(), 0)?;
+        assert_eq!(layout.len, 51);
+        assert_eq!(
+            layout
+                .sub_packets
+                .iter()
+                .map(|l| l.offset)
+                .collect::<Vec<_>>(),
+            vec![18, 29, 40]
+        );
+        assert!(packet
+            .disassemble(&layout)
+            .starts_with("     0  v7 t3 Maximum (max), length type 1, 3 sub-packets = 3\n"));
+
+        let source = format!("{}1{}", "(2 * ".repeat(100), ")".repeat(100));
+        let input = as_input(&compile(&source)?)?;
+        let (packet, layout) = Packet::decode_bits(input.view_bits::<Msb0>(), 0)?;
+        let disassembly = packet.disassemble(&layout);
+        assert_eq!(disassembly.lines().count(), 201);
+        assert!(disassembly
+            .lines()
+            .next()
+            .is_some_and(|line| line.ends_with(&format!("= {}", packet.calculate()))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_to_sexpr() -> Result<()> {
+        let packet = Packet::decode_bytes(&as_input(&compile("1 + 2 * 3")?)?)?;
+        assert_eq!(packet.to_sexpr(), "(+ 1 (* 2 3))");
+
+        let packet = Packet::decode_bytes(&as_input("9C0141080250320F1802104A08")?)?;
+        assert_eq!(packet.to_sexpr(), "(== (+ 1 3) (* 2 2))");
         Ok(())
     }
 }
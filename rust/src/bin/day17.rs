@@ -3,11 +3,12 @@ use std::env;
 use std::fs::File;
 use std::io::prelude::*;
 use std::io::BufReader;
+use std::ops::RangeInclusive;
 use std::str::FromStr;
 
 use anyhow::{anyhow, Context, Result};
 
-use utils::measure;
+use utils::{flag, measure};
 
 type Input = Area;
 
@@ -26,12 +27,26 @@ impl Area {
         max(self.x_range[0], self.x_range[1])
     }
 
+    fn min_x(&self) -> i32 {
+        min(self.x_range[0], self.x_range[1])
+    }
+
     fn min_y(&self) -> i32 {
         min(self.y_range[0], self.y_range[1])
     }
+
+    fn x_bounds(&self) -> (i64, i64) {
+        let [a, b] = self.x_range;
+        (min(a, b) as i64, max(a, b) as i64)
+    }
+
+    fn y_bounds(&self) -> (i64, i64) {
+        let [a, b] = self.y_range;
+        (min(a, b) as i64, max(a, b) as i64)
+    }
 }
 
-#[derive(Clone, Copy, Eq, PartialEq, Hash)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
 struct Pos {
     x: i32,
     y: i32,
@@ -55,98 +70,383 @@ impl Velocity {
     }
 }
 
-fn simulate(target: &Area, velocity: &Velocity) -> (bool, Vec<Pos>) {
-    let mut trajectory = vec![];
-    let mut pos = Pos::new(0, 0);
-    let mut vel = *velocity;
+/// How the probe moves: each step its horizontal speed slows toward 0 by `drag` and its vertical
+/// speed drops by `gravity`.
+#[derive(Debug, Clone, Copy)]
+struct Physics {
+    drag: i32,
+    gravity: i32,
+    origin: Pos,
+}
 
-    loop {
-        pos = Pos::new(pos.x + vel.x, pos.y + vel.y);
-        trajectory.push(pos);
+impl Physics {
+    const PUZZLE: Physics = Physics {
+        drag: 1,
+        gravity: 1,
+        origin: Pos { x: 0, y: 0 },
+    };
+
+    /// Physics the probe always comes down in, so every launch ends.
+    fn new(drag: i32, gravity: i32, origin: Pos) -> Result<Self> {
+        if drag < 0 {
+            return Err(anyhow!("Drag of {} would speed the probe up", drag));
+        }
+        if gravity < 1 {
+            return Err(anyhow!(
+                "Gravity of {} never brings the probe down",
+                gravity
+            ));
+        }
+        Ok(Physics {
+            drag,
+            gravity,
+            origin,
+        })
+    }
+
+    /// Fails once the probe is too far out or too fast for an `i32`.
+    fn step(&self, pos: &Pos, vel: &Velocity) -> Result<(Pos, Velocity)> {
+        let overflow = || anyhow!("Probe at {:?} with {:?} left the i32 range", pos, vel);
+        let pos = Pos::new(
+            pos.x.checked_add(vel.x).ok_or_else(overflow)?,
+            pos.y.checked_add(vel.y).ok_or_else(overflow)?,
+        );
+        let v_x = vel.x.signum() * max(vel.x.checked_abs().ok_or_else(overflow)? - self.drag, 0);
+        let v_y = vel.y.checked_sub(self.gravity).ok_or_else(overflow)?;
+        Ok((pos, Velocity::new(v_x, v_y)))
+    }
 
-        if target.within(&pos) {
-            break (true, trajectory);
+    /// Launches the probe with `velocity` until it has been within each area of `route` in
+    /// order, or can't reach the next one anymore. A position within several consecutive areas
+    /// counts for all of them. Returns whether the route was completed and the positions after
+    /// each step.
+    fn simulate(&self, velocity: &Velocity, route: &[&Area]) -> Result<(bool, Vec<Pos>)> {
+        let mut trajectory = vec![];
+        let mut pos = self.origin;
+        let mut vel = *velocity;
+        let mut route = route.iter().peekable();
+
+        loop {
+            let (n_pos, n_vel) = self.step(&pos, &vel)?;
+            pos = n_pos;
+            vel = n_vel;
+            trajectory.push(pos);
+
+            while route.next_if(|area| area.within(&pos)).is_some() {}
+            let next = match route.peek() {
+                Some(next) => next,
+                None => break Ok((true, trajectory)),
+            };
+
+            // Drag never turns the probe around and gravity keeps it falling once it does.
+            let passed_x =
+                (vel.x >= 0 && pos.x > next.max_x()) || (vel.x <= 0 && pos.x < next.min_x());
+            if passed_x || pos.y < next.min_y() && vel.y < 0 {
+                break Ok((false, trajectory));
+            }
         }
+    }
 
-        let n_v_x = if vel.x == 0 {
-            0
-        } else if vel.x > 0 {
-            vel.x - 1
-        } else {
-            vel.x + 1
+    /// All velocities following `route`, except those coming back down onto the launch height
+    /// within an area after their horizontal speed ran out, of which there can be infinitely
+    /// many.
+    fn velocities(&self, route: &[&Area]) -> Result<Vec<Velocity>> {
+        let distance = |a: i32, b: i32, o: i32| {
+            let (a, b, o) = (a as i64, b as i64, o as i64);
+            max((a - o).abs(), (b - o).abs())
         };
-        vel = Velocity::new(n_v_x, vel.y - 1);
+        let d_x = route
+            .iter()
+            .map(|a| distance(a.x_range[0], a.x_range[1], self.origin.x))
+            .max()
+            .unwrap_or(0);
+        let d_y = route
+            .iter()
+            .map(|a| distance(a.y_range[0], a.y_range[1], self.origin.y))
+            .max()
+            .unwrap_or(0);
+
+        // Going any faster the probe overshoots on its first step, or skips past every area
+        // when it comes back down through the launch height.
+        let fastest = max(d_x, d_y)
+            .checked_mul(self.gravity as i64)
+            .and_then(|fastest| i32::try_from(fastest).ok())
+            .context("Launch speeds don't fit in an i32")?;
+        let (d_x, d_y) = (d_x as i32, d_y as i32);
+
+        let mut velocities = vec![];
+        for y in -d_y..=fastest {
+            for x in -d_x..=d_x {
+                let vel = Velocity::new(x, y);
+                if self.simulate(&vel, route)?.0 {
+                    velocities.push(vel);
+                }
+            }
+        }
+        Ok(velocities)
+    }
+}
+
+fn triangular(n: i64) -> i64 {
+    n * (n + 1) / 2
+}
+
+/// Largest `n` for which `triangular(n) <= value`.
+fn triangular_root(value: i64) -> i64 {
+    if value < 0 {
+        return -1;
+    }
+    let mut n = (((8 * value + 1) as f64).sqrt() as i64 - 1) / 2;
+    while triangular(n + 1) <= value {
+        n += 1;
+    }
+    while triangular(n) > value {
+        n -= 1;
+    }
+    n
+}
+
+/// First value in `lo..hi` for which `pred` doesn't hold, given it holds on a prefix of the range.
+fn partition_point(mut lo: i64, mut hi: i64, pred: impl Fn(i64) -> bool) -> i64 {
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if pred(mid) {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+    lo
+}
+
+fn y_at(vy: i64, step: i64) -> i64 {
+    vy * step - triangular(step - 1)
+}
 
-        if pos.x > target.max_x() || pos.y < target.min_y() && vel.y < 0 {
-            break (false, trajectory);
+/// Steps at which a probe launched with vertical speed `vy` is between `y_min` and `y_max`: at
+/// most one range while rising up to step `vy`, and one while falling after it.
+fn y_steps(vy: i64, (y_min, y_max): (i64, i64)) -> Vec<RangeInclusive<i64>> {
+    let mut steps = vec![];
+
+    let rising = vy + 1;
+    let first = partition_point(1, rising, |t| y_at(vy, t) < y_min);
+    let last = partition_point(1, rising, |t| y_at(vy, t) <= y_max) - 1;
+    if first <= last {
+        steps.push(first..=last);
+    }
+
+    // Past the apex the probe drops by at least one more each step, so it's below `y_min` by then.
+    let falling = max(vy + 1, 1);
+    let below = 2 * max(vy, 0) + y_min.abs() + 2;
+    let first = partition_point(falling, below, |t| y_at(vy, t) > y_max);
+    let last = partition_point(falling, below, |t| y_at(vy, t) >= y_min) - 1;
+    if first <= last {
+        steps.push(first..=last);
+    }
+
+    steps
+}
+
+/// Positive horizontal speeds that put the probe between `x_min >= 1` and `x_max` at `step`. The
+/// probe's x after a step only grows with its speed, so these form a range.
+fn x_speeds(step: i64, x_min: i64, x_max: i64) -> Option<RangeInclusive<i64>> {
+    // Speeds up to `step` have stopped at a triangular number, faster ones are still moving.
+    let stopped = triangular_root(x_min - 1) + 1;
+    let slowest = if stopped <= step {
+        stopped
+    } else {
+        (x_min + triangular(step - 1) + step - 1) / step
+    };
+    let stopped = triangular_root(x_max);
+    let fastest = if stopped < step {
+        stopped
+    } else {
+        (x_max + triangular(step - 1)) / step
+    };
+
+    (slowest <= fastest).then_some(slowest..=fastest)
+}
+
+/// Positive horizontal speeds that put the probe between `x_min >= 1` and `x_max` at any of
+/// `steps`, as sorted disjoint ranges.
+fn x_speed_ranges(
+    steps: &[RangeInclusive<i64>],
+    x_min: i64,
+    x_max: i64,
+) -> Vec<RangeInclusive<i64>> {
+    // Once every speed reaching `x_max` has stopped the speeds don't change from step to step.
+    let settled = triangular_root(x_max) + 1;
+    let mut speeds = steps
+        .iter()
+        .flat_map(|steps| *steps.start()..=min(*steps.end(), max(settled, *steps.start())))
+        .filter_map(|step| x_speeds(step, x_min, x_max))
+        .collect::<Vec<_>>();
+    speeds.sort_by_key(|speeds| *speeds.start());
+
+    let mut merged: Vec<RangeInclusive<i64>> = vec![];
+    for speeds in speeds {
+        match merged.last_mut() {
+            Some(last) if *speeds.start() <= *last.end() + 1 => {
+                *last = *last.start()..=max(*last.end(), *speeds.end())
+            }
+            _ => merged.push(speeds),
         }
     }
+    merged
 }
 
-fn solve(input: &Input) -> (i32, i32) {
-    let mut trajectories = vec![];
+/// Horizontal speeds, of either sign, that hit the target at any of `steps`.
+fn hitting_x_speeds(target: &Area, steps: &[RangeInclusive<i64>]) -> Vec<RangeInclusive<i64>> {
+    if steps.is_empty() {
+        return vec![];
+    }
+
+    let (x_min, x_max) = target.x_bounds();
+    let mut speeds = if x_min <= -1 {
+        x_speed_ranges(steps, max(-x_max, 1), -x_min)
+            .into_iter()
+            .rev()
+            .map(|speeds| -speeds.end()..=-speeds.start())
+            .collect()
+    } else {
+        vec![]
+    };
+    if x_min <= 0 && 0 <= x_max {
+        speeds.push(0..=0);
+    }
+    if x_max >= 1 {
+        speeds.extend(x_speed_ranges(steps, max(x_min, 1), x_max));
+    }
+    speeds
+}
+
+/// Highest point and number of launch velocities that hit the target, found per vertical speed
+/// from the steps at which it's within the target's height.
+fn solve(input: &Input) -> Result<(i64, i64)> {
+    let (x_min, x_max) = input.x_bounds();
+    let (y_min, y_max) = input.y_bounds();
+
+    // Every upward launch comes back to height 0, so one stopping within the target hits it.
+    let stops_within = |lo: i64, hi: i64| triangular_root(hi) > triangular_root(lo - 1);
+    let stops_in_target = (x_min <= 0 && 0 <= x_max)
+        || (x_max >= 1 && stops_within(max(x_min, 1), x_max))
+        || (x_min <= -1 && stops_within(max(-x_max, 1), -x_min));
+    if y_min <= 0 && 0 <= y_max && stops_in_target {
+        return Err(anyhow!(
+            "Infinitely many velocities hit a target at launch height"
+        ));
+    }
 
     let mut max_y = 0;
     let mut hits = 0;
-    // TODO Lots of room for optimization here by reducing iterations
-    for y in -1000..1000 {
-        for x in 1..1000 {
-            let vel = Velocity::new(x, y);
-            let (hit, trajectory) = simulate(input, &vel);
-            if hit {
-                max_y = max(max_y, trajectory.iter().map(|p| p.y).max().unwrap_or(0));
-                hits += 1;
-                trajectories.push(trajectory);
-            }
+    let fastest = max(max(y_min.abs(), y_max.abs()), max(x_min.abs(), x_max.abs()));
+    for vy in min(y_min, 0)..=fastest {
+        let speeds = hitting_x_speeds(input, &y_steps(vy, (y_min, y_max)));
+        if !speeds.is_empty() {
+            max_y = max(max_y, triangular(max(vy, 0)));
+            hits += speeds.iter().map(|s| s.end() - s.start() + 1).sum::<i64>();
         }
     }
 
     #[cfg(feature = "print")]
-    print(input, &trajectories);
-    (max_y, hits)
+    {
+        let mut trajectories = vec![];
+        for vy in min(y_min, 0)..=fastest {
+            for speeds in hitting_x_speeds(input, &y_steps(vy, (y_min, y_max))) {
+                for vx in speeds {
+                    let vel = Velocity::new(vx as i32, vy as i32);
+                    trajectories.push(Physics::PUZZLE.simulate(&vel, &[input])?.1);
+                }
+            }
+        }
+        print(input, &trajectories);
+    }
+    Ok((max_y, hits))
+}
+
+/// Physics from `--drag`, `--gravity` and `--origin x,y`, if any of them is given.
+fn physics() -> Result<Option<Physics>> {
+    let (drag, gravity, origin) = (flag("--drag"), flag("--gravity"), flag("--origin"));
+    if drag.is_none() && gravity.is_none() && origin.is_none() {
+        return Ok(None);
+    }
+
+    let puzzle = Physics::PUZZLE;
+    let origin = match origin {
+        Some(origin) => {
+            let (x, y) = origin.split_once(',').context("Origin should be x,y")?;
+            Pos::new(x.trim().parse()?, y.trim().parse()?)
+        }
+        None => puzzle.origin,
+    };
+    let drag = drag.map_or(Ok(puzzle.drag), |drag| drag.parse())?;
+    let gravity = gravity.map_or(Ok(puzzle.gravity), |gravity| gravity.parse())?;
+    Physics::new(drag, gravity, origin).map(Some)
+}
+
+/// Areas to pass through after the target from `--route`, like "x=20..40, y=-40..-30; x=...".
+fn route() -> Result<Option<Vec<Area>>> {
+    flag("--route")
+        .map(|route| {
+            route
+                .split(';')
+                .map(|area| format!("area: {}", area.trim()).parse())
+                .collect()
+        })
+        .transpose()
 }
 
 fn main() -> Result<()> {
     measure(|| {
         let input = input()?;
-        let (part1, part2) = solve(&input);
+        let (part1, part2) = solve(&input)?;
         println!("Part1: {}", part1);
         println!("Part2: {}", part2);
+
+        let (physics, route) = (physics()?, route()?);
+        if physics.is_some() || route.is_some() {
+            let route = route.unwrap_or_default();
+            let route = [&input].into_iter().chain(&route).collect::<Vec<_>>();
+            let velocities = physics.unwrap_or(Physics::PUZZLE).velocities(&route)?;
+            println!("Velocities: {}", velocities.len());
+        }
         Ok(())
     })
 }
 
+/// Parses `axis=min..max`.
+fn parse_range(s: &str, axis: &str) -> Result<[i32; 2]> {
+    let range = s
+        .trim()
+        .strip_prefix(axis)
+        .and_then(|s| s.strip_prefix('='))
+        .with_context(|| format!("Expected {}= in {:?}", axis, s))?;
+    let (min, max) = range
+        .split_once("..")
+        .with_context(|| format!("Expected min..max in {:?}", s))?;
+    let (min, max) = (min.parse::<i32>()?, max.parse::<i32>()?);
+    if min > max {
+        return Err(anyhow!("Empty range in {:?}", s));
+    }
+    Ok([min, max])
+}
+
 impl FromStr for Area {
     type Err = anyhow::Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut splits = s.split(":");
-        splits.next();
-        let mut splits = splits
-            .next()
-            .ok_or_else(|| anyhow!("Missing :"))?
-            .trim()
-            .split(", ");
-
-        let x_range = splits.next().unwrap().split("=").skip(1).next().unwrap();
-        let x_range = x_range
-            .split("..")
-            .map(|s| s.parse::<i32>().unwrap())
-            .collect::<Vec<i32>>();
-        let x_range = [x_range[0], x_range[1]];
-
-        let y_range = splits.next().unwrap().split("=").skip(1).next().unwrap();
-        let y_range = y_range
-            .split("..")
-            .map(|s| s.parse::<i32>().unwrap())
-            .collect::<Vec<i32>>();
-        let y_range = [y_range[0], y_range[1]];
-
-        Ok(Area { x_range, y_range })
+        let (_, ranges) = s.split_once(':').context("Missing :")?;
+        let (x_range, y_range) = ranges
+            .split_once(',')
+            .with_context(|| format!("Expected x and y ranges in {:?}", s))?;
+        Ok(Area {
+            x_range: parse_range(x_range, "x")?,
+            y_range: parse_range(y_range, "y")?,
+        })
     }
 }
 
 fn read_input<R: Read>(reader: BufReader<R>) -> Result<Input> {
-    Ok(reader.lines().next().unwrap()?.parse::<Area>()?)
+    reader.lines().next().context("No target area")??.parse()
 }
 
 fn input() -> Result<Input> {
@@ -215,13 +515,241 @@ mod tests {
 
     #[test]
     fn test_part1() -> Result<()> {
-        assert_eq!(solve(&as_input(INPUT)?).0, 45);
+        assert_eq!(solve(&as_input(INPUT)?)?.0, 45);
         Ok(())
     }
 
     #[test]
     fn test_part2() -> Result<()> {
-        assert_eq!(solve(&as_input(INPUT)?).1, 112);
+        assert_eq!(solve(&as_input(INPUT)?)?.1, 112);
+        Ok(())
+    }
+
+    fn brute_force(target: &Area) -> (i64, i64) {
+        let (x_min, x_max) = target.x_bounds();
+        let (y_min, y_max) = target.y_bounds();
+        let fastest = max(max(y_min.abs(), y_max.abs()), max(x_min.abs(), x_max.abs()));
+
+        let mut max_y = 0;
+        let mut hits = 0;
+        for vy in min(y_min, 0)..=fastest {
+            for vx in -x_min.abs().max(x_max.abs())..=x_min.abs().max(x_max.abs()) {
+                if Physics::PUZZLE
+                    .simulate(&Velocity::new(vx as i32, vy as i32), &[target])
+                    .unwrap()
+                    .0
+                {
+                    max_y = max(max_y, triangular(max(vy, 0)));
+                    hits += 1;
+                }
+            }
+        }
+        (max_y, hits)
+    }
+
+    /// Counts the velocities hitting a target right of and below the launch from the steps at
+    /// which each speed alone is within the target's width or height, found step by step. A
+    /// velocity hits when both of its step ranges overlap.
+    fn count_by_step_ranges(target: &Area) -> i64 {
+        let (x_min, x_max) = target.x_bounds();
+        let (y_min, y_max) = target.y_bounds();
+        assert!(x_min >= 1 && y_max < 0);
+
+        // A probe stopping within the width stays there for good.
+        let mut x_firsts = vec![];
+        let mut x_lasts = vec![];
+        for vx in 1..=x_max {
+            let (mut x, mut v, mut t) = (0, vx, 0);
+            while x < x_min && v > 0 {
+                (x, v, t) = (x + v, v - 1, t + 1);
+            }
+            if x < x_min || x > x_max {
+                continue;
+            }
+            x_firsts.push(t);
+            while v > 0 && x + v <= x_max {
+                (x, v, t) = (x + v, v - 1, t + 1);
+            }
+            x_lasts.push(if v == 0 { i64::MAX } else { t });
+        }
+        x_firsts.sort_unstable();
+        x_lasts.sort_unstable();
+
+        let mut hits = 0;
+        for vy in y_min..-y_min {
+            // Launched upwards the probe is back at height 0 after 2 vy + 1 steps, falling at
+            // vy + 1.
+            let (mut y, mut v, mut t) = if vy > 0 {
+                (0, -vy - 1, 2 * vy + 1)
+            } else {
+                (0, vy, 0)
+            };
+            while y > y_max {
+                (y, v, t) = (y + v, v - 1, t + 1);
+            }
+            if y < y_min {
+                continue;
+            }
+            let first = t;
+            while y + v >= y_min {
+                (y, v, t) = (y + v, v - 1, t + 1);
+            }
+
+            // Either the width is reached after leaving the height, or left before reaching it.
+            let late = x_firsts.len() - x_firsts.partition_point(|&x_first| x_first <= t);
+            let early = x_lasts.partition_point(|&x_last| x_last < first);
+            hits += (x_firsts.len() - late - early) as i64;
+        }
+        hits
+    }
+
+    #[test]
+    fn test_triangular_root() {
+        for n in 0..2000 {
+            assert_eq!(triangular_root(triangular(n)), n);
+            assert_eq!(triangular_root(triangular(n + 1) - 1), n);
+        }
+        assert_eq!(triangular_root(-1), -1);
+    }
+
+    #[test]
+    fn test_against_simulation() -> Result<()> {
+        let mut seed = 17_u64;
+        let mut random = |n: i32| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) as i32 % n
+        };
+
+        for _ in 0..300 {
+            let x = random(61) - 30;
+            let y = random(61) - 30;
+            let target = Area {
+                x_range: [x, x + random(10)],
+                y_range: [y, y + random(10)],
+            };
+            let (y_min, y_max) = target.y_bounds();
+            if y_min <= 0 && 0 <= y_max {
+                continue;
+            }
+            assert_eq!(
+                solve(&target)?,
+                brute_force(&target),
+                "{:?} {:?}",
+                target.x_range,
+                target.y_range
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for (source, error) in [
+            ("garbage", "Missing :"),
+            ("area: x=1..2", "Expected x and y ranges"),
+            ("area: y=1..2, x=1..2", "Expected x="),
+            ("area: x=1, y=1..2", "Expected min..max"),
+            ("area: x=1..a, y=1..2", "invalid digit"),
+            ("area: x=1..2, y=3..-3", "Empty range"),
+        ] {
+            let message = source.parse::<Area>().map(|_| ()).unwrap_err().to_string();
+            assert!(message.starts_with(error), "{}: {}", source, message);
+        }
+        assert!(as_input("").is_err());
+    }
+
+    #[test]
+    fn test_unbounded_targets() -> Result<()> {
+        assert!(solve(&as_input("target area: x=5..7, y=-3..2")?).is_err());
+        assert!(solve(&as_input("target area: x=-1..1, y=0..0")?).is_err());
+        // No speed stops between 7 and 9, so only a few launches make it while still moving.
+        let (_, hits) = solve(&as_input("target area: x=7..9, y=-2..2")?)?;
+        assert_eq!(
+            hits,
+            brute_force(&as_input("target area: x=7..9, y=-2..2")?).1
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_physics() -> Result<()> {
+        let target = as_input(INPUT)?;
+        assert_eq!(Physics::PUZZLE.velocities(&[&target])?.len(), 112);
+
+        let physics = Physics::new(0, 2, Pos::new(0, 0))?;
+        let below = Area {
+            x_range: [10, 10],
+            y_range: [-5, -5],
+        };
+        assert_eq!(
+            physics.simulate(&Velocity::new(2, 3), &[&below])?,
+            (
+                true,
+                vec![
+                    Pos::new(2, 3),
+                    Pos::new(4, 4),
+                    Pos::new(6, 3),
+                    Pos::new(8, 0),
+                    Pos::new(10, -5)
+                ]
+            )
+        );
+
+        // Moving the launch and the target together doesn't change anything.
+        let moved = Area {
+            x_range: [120, 130],
+            y_range: [-510, -505],
+        };
+        let velocities = Physics::new(1, 1, Pos::new(100, -500))?.velocities(&[&moved])?;
+        assert_eq!(velocities, Physics::PUZZLE.velocities(&[&target])?);
+
+        assert!(Physics::new(-1, 1, Pos::new(0, 0)).is_err());
+        assert!(Physics::new(1, 0, Pos::new(0, 0)).is_err());
+
+        // Too far for the launch speeds, or the probe's climb, to fit in an i32.
+        let far = Area {
+            x_range: [0, 1],
+            y_range: [i32::MIN, i32::MIN + 1],
+        };
+        assert!(Physics::PUZZLE.velocities(&[&far]).is_err());
+        let heavy = Physics::new(1, 1 << 27, Pos::new(0, 0))?;
+        assert!(heavy.velocities(&[&target]).is_err());
+        assert!(Physics::PUZZLE
+            .simulate(&Velocity::new(0, 1 << 16), &[&far])
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_route() -> Result<()> {
+        let a = as_input(INPUT)?;
+        let b = as_input("target area: x=20..40, y=-40..-30")?;
+
+        let through_a = Physics::PUZZLE.velocities(&[&a])?;
+        let through_b = Physics::PUZZLE.velocities(&[&b])?;
+        let route = Physics::PUZZLE.velocities(&[&a, &b])?;
+        assert!(!route.is_empty());
+        assert!(route.len() < through_a.len());
+        assert!(route
+            .iter()
+            .all(|v| through_a.contains(v) && through_b.contains(v)));
+
+        // The probe never climbs back up from the lower area.
+        assert!(Physics::PUZZLE.velocities(&[&b, &a])?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_large_target() -> Result<()> {
+        let target = as_input("target area: x=1000000..1100000, y=-1000000..-900000")?;
+        let (max_y, hits) = solve(&target)?;
+        assert_eq!(max_y, triangular(999_999));
+        assert_eq!(hits, count_by_step_ranges(&target));
+        assert_eq!(hits, 16_143_286_181);
+
+        assert_eq!(count_by_step_ranges(&as_input(INPUT)?), 112);
         Ok(())
     }
 }
@@ -1,315 +1,470 @@
-use std::cell::RefCell;
 use std::cmp;
 use std::env;
 use std::fmt;
 use std::fs::File;
 use std::io::prelude::*;
 use std::io::BufReader;
-use std::num::ParseIntError;
+use std::iter::Sum;
+use std::ops::Add;
 use std::str::FromStr;
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
+use num_bigint::BigUint;
+use rayon::prelude::*;
 
+use utils::combinatorics::combinations;
 use utils::measure;
 
 type Input = Vec<Number>;
 
+/// A regular number inside `depth` pairs.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+struct Leaf {
+    value: i32,
+    depth: u32,
+}
+
+/// A snailfish number as its regular numbers from left to right. Their depths are enough to tell
+/// which pairs they're in: each pair holds whatever comes first at one level deeper, then
+/// whatever comes next.
 #[derive(Debug, Clone, Eq, PartialEq)]
-enum Number {
-    Literal(i32),
-    Pair(RefCell<Box<Number>>, RefCell<Box<Number>>),
+struct Number {
+    leaves: Vec<Leaf>,
 }
 
 impl Number {
-    fn is_literal(&self) -> bool {
-        match self {
-            Number::Literal(_) => true,
-            _ => false,
-        }
-    }
+    /// Pairs nested inside this many pairs explode.
+    const MAX_DEPTH: u32 = 4;
 
-    fn literal(&self) -> i32 {
-        match self {
-            &Number::Literal(literal) => literal,
-            _ => panic!("Not a literal"),
-        }
-    }
+    /// Most the regular numbers of a parsed number can add up to. Reducing never increases that
+    /// total, so the sum of two numbers and every step reducing it can't overflow.
+    const MAX_TOTAL: i32 = i32::MAX / 2;
 
+    /// Reduces any number. A sum of two reduced numbers has its pairs nested at most one level
+    /// too deep, and those go the fast way; anything deeper goes one step at a time.
     fn reduce(&self) -> Number {
-        fn explode(n: &Number, depth: i32) -> Option<(bool, Option<i32>, Option<i32>)> {
-            match n {
-                Number::Literal(_) => None,
-                Number::Pair(p1, p2) => {
-                    if depth >= 4 {
-                        return Some((
-                            true,
-                            Some(p1.borrow().literal()),
-                            Some(p2.borrow().literal()),
-                        ));
-                    }
-
-                    let fallout = explode(&p1.borrow(), depth + 1);
-                    if let Some((exploded, fa_left, fa_right)) = fallout {
-                        if exploded {
-                            p1.replace(Box::new(Number::Literal(0)));
-                        }
-
-                        if let Some(fa_right) = fa_right {
-                            if p2.borrow().is_literal() {
-                                let lit = p2.borrow().literal();
-                                p2.replace(Box::new(Number::Literal(lit + fa_right)));
-                            } else {
-                                if !add_fallout_right(p2, fa_right) {
-                                    return Some((false, fa_left, Some(fa_right)));
-                                }
-                            }
-                        }
-                        return Some((false, fa_left, None));
-                    }
+        if self.depth() > Self::MAX_DEPTH + 1 {
+            return self
+                .reduction()
+                .last()
+                .map_or_else(|| self.clone(), |(_, n)| n);
+        }
 
-                    let fallout = explode(&p2.borrow(), depth + 1);
-                    if let Some((exploded, fa_left, fa_right)) = fallout {
-                        if exploded {
-                            p2.replace(Box::new(Number::Literal(0)));
-                        }
-
-                        if let Some(fa_left) = fa_left {
-                            if p1.borrow().is_literal() {
-                                let lit = p1.borrow().literal();
-                                p1.replace(Box::new(Number::Literal(lit + fa_left)));
-                            } else {
-                                if !add_fallout_left(p1, fa_left) {
-                                    return Some((false, Some(fa_left), fa_right));
-                                }
-                            }
-                        }
-                        return Some((false, None, fa_right));
-                    }
-                    None
+        // Explosions don't nest anything deeper, so they can all happen first from left to right,
+        // carrying the right value over to the next regular number.
+        let mut exploded: Vec<Leaf> = Vec::with_capacity(self.leaves.len());
+        let mut carry = 0;
+        let mut leaves = self.leaves.iter();
+        while let Some(&leaf) = leaves.next() {
+            let value = leaf.value + carry;
+            carry = 0;
+
+            if leaf.depth > Self::MAX_DEPTH {
+                if let Some(left) = exploded.last_mut() {
+                    left.value += value;
                 }
+                carry = leaves.next().map_or(0, |right| right.value);
+                exploded.push(Leaf {
+                    value: 0,
+                    depth: leaf.depth - 1,
+                });
+            } else {
+                exploded.push(Leaf { value, ..leaf });
             }
         }
 
-        fn add_fallout_right(n: &RefCell<Box<Number>>, add: i32) -> bool {
-            if n.borrow().is_literal() {
-                let lit = n.borrow().literal();
-                n.replace(Box::new(Number::Literal(lit + add)));
-                true
-            } else {
-                match &**n.borrow() {
-                    Number::Pair(p1, p2) => {
-                        if add_fallout_right(p1, add) {
-                            return true;
-                        }
-                        add_fallout_right(p2, add)
-                    }
-                    _ => unreachable!(),
-                }
+        // Everything on `reduced` is below 10 so the next pending number is always the leftmost
+        // that could split. Splitting too deep explodes right away, which can push the number
+        // before it to 10 and back to pending.
+        let mut pending = exploded;
+        pending.reverse();
+        let mut reduced: Vec<Leaf> = Vec::with_capacity(pending.len());
+        while let Some(leaf) = pending.pop() {
+            if leaf.value < 10 {
+                reduced.push(leaf);
+                continue;
             }
-        }
 
-        fn add_fallout_left(n: &RefCell<Box<Number>>, add: i32) -> bool {
-            if n.borrow().is_literal() {
-                let lit = n.borrow().literal();
-                n.replace(Box::new(Number::Literal(lit + add)));
-                true
+            let (left, right) = (leaf.value / 2, leaf.value - leaf.value / 2);
+            if leaf.depth < Self::MAX_DEPTH {
+                let depth = leaf.depth + 1;
+                pending.push(Leaf {
+                    value: right,
+                    depth,
+                });
+                pending.push(Leaf { value: left, depth });
             } else {
-                match &**n.borrow() {
-                    Number::Pair(p1, p2) => {
-                        if add_fallout_left(p2, add) {
-                            return true;
-                        }
-                        add_fallout_left(p1, add)
-                    }
-                    _ => unreachable!(),
+                if let Some(next) = pending.last_mut() {
+                    next.value += right;
+                }
+                pending.push(Leaf { value: 0, ..leaf });
+                if let Some(previous) = reduced.pop() {
+                    pending.push(Leaf {
+                        value: previous.value + left,
+                        ..previous
+                    });
                 }
             }
         }
 
-        fn split(n: &Number) -> bool {
-            if n.is_literal() && n.literal() >= 10 {
-                true
-            } else {
-                match n {
-                    Number::Pair(p1, p2) => {
-                        if split_cell(p1) {
-                            return true;
-                        }
-                        split_cell(p2)
-                    }
-                    _ => unreachable!(),
-                }
-            }
+        Number { leaves: reduced }
+    }
+
+    /// The pair of `self` and `other`, before reducing it.
+    fn join(&self, other: &Number) -> Number {
+        Number {
+            leaves: self
+                .leaves
+                .iter()
+                .chain(other.leaves.iter())
+                .map(|leaf| Leaf {
+                    depth: leaf.depth + 1,
+                    ..*leaf
+                })
+                .collect(),
         }
+    }
 
-        fn split_cell(n: &RefCell<Box<Number>>) -> bool {
-            if n.borrow().is_literal() {
-                let lit = n.borrow().literal();
-                if lit >= 10 {
-                    let l1 = lit / 2;
-                    n.replace(Box::new(Number::Pair(
-                        RefCell::new(Box::new(Number::Literal(l1))),
-                        RefCell::new(Box::new(Number::Literal(lit - l1))),
-                    )));
-                    true
-                } else {
-                    false
-                }
-            } else {
-                match &**n.borrow() {
-                    Number::Pair(p1, p2) => {
-                        if split_cell(p1) {
-                            return true;
-                        }
-                        split_cell(p2)
-                    }
-                    _ => unreachable!(),
-                }
-            }
+    fn add_all(numbers: &Vec<Number>) -> Number {
+        numbers.iter().sum()
+    }
+
+    /// Adds two numbers after checking they're reduced, as `+` expects them to be.
+    fn checked_add(&self, other: &Number) -> Result<Number> {
+        self.check_reduced()?;
+        other.check_reduced()?;
+        Ok(self + other)
+    }
+
+    /// Fails with the action that would reduce the number further, if there's one.
+    fn check_reduced(&self) -> Result<()> {
+        match self.step() {
+            Some((action, _)) => Err(anyhow!("Number isn't reduced: {}", action)),
+            None => Ok(()),
         }
+    }
 
-        let n = self.clone();
+    fn is_reduced(&self) -> bool {
+        self.check_reduced().is_ok()
+    }
 
-        loop {
-            if let Some(_) = explode(&n, 0) {
-                continue;
-            }
-            if split(&n) {
-                continue;
+    /// How many pairs the deepest regular number is inside.
+    fn depth(&self) -> u32 {
+        self.leaves.iter().map(|l| l.depth).max().unwrap_or(0)
+    }
+
+    /// How many regular numbers there are.
+    fn leaf_count(&self) -> usize {
+        self.leaves.len()
+    }
+
+    /// Each level of pairs multiplies by up to 3, so deep numbers get large.
+    fn magnitude(&self) -> BigUint {
+        // Pairs are complete as soon as the two numbers on top are at the same depth.
+        let mut stack: Vec<(BigUint, u32)> = vec![];
+        for leaf in &self.leaves {
+            stack.push((BigUint::from(leaf.value as u32), leaf.depth));
+            while let [.., (_, left), (_, right)] = &stack[..] {
+                if left != right {
+                    break;
+                }
+                let (right, depth) = stack.pop().unwrap_or_default();
+                let (left, _) = stack.pop().unwrap_or_default();
+                stack.push((left * 3_u32 + right * 2_u32, depth - 1));
             }
-            break;
         }
-        n
+        stack
+            .pop()
+            .map(|(magnitude, _)| magnitude)
+            .unwrap_or_default()
     }
+}
+
+impl Add for Number {
+    type Output = Number;
 
-    fn add(&self, other: &Number) -> Number {
-        Number::Pair(
-            RefCell::new(Box::new(self.clone())),
-            RefCell::new(Box::new(other.clone())),
-        )
+    /// The reduced sum of two reduced numbers.
+    fn add(self, other: Number) -> Number {
+        &self + &other
     }
+}
 
-    fn add_all(numbers: &Vec<Number>) -> Number {
-        let mut added = numbers[0].clone();
+impl Add<&Number> for &Number {
+    type Output = Number;
 
-        for n in numbers.iter().skip(1) {
-            added = added.add(n);
-            added = added.reduce();
-        }
+    fn add(self, other: &Number) -> Number {
+        self.join(other).reduce()
+    }
+}
 
-        added
+impl Sum for Number {
+    /// # Panics
+    ///
+    /// Snailfish numbers have no zero, so there has to be at least one number.
+    fn sum<I: Iterator<Item = Number>>(mut iter: I) -> Number {
+        let first = iter.next().expect("Nothing to sum");
+        iter.fold(first, |sum, n| &sum + &n)
     }
+}
 
-    fn magnitude(&self) -> i32 {
-        match self {
-            &Number::Literal(literal) => literal,
-            Number::Pair(p1, p2) => p1.borrow().magnitude() * 3 + p2.borrow().magnitude() * 2,
-        }
+impl<'a> Sum<&'a Number> for Number {
+    fn sum<I: Iterator<Item = &'a Number>>(mut iter: I) -> Number {
+        let first = iter.next().expect("Nothing to sum").clone();
+        iter.fold(first, |sum, n| &sum + n)
     }
 }
 
-impl fmt::Display for Number {
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Side {
+    Left,
+    Right,
+}
+
+/// What a reduction step did, at the path to the exploding pair or splitting number from the
+/// outermost pair.
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Action {
+    Explode {
+        path: Vec<Side>,
+        left: i32,
+        right: i32,
+    },
+    Split {
+        path: Vec<Side>,
+        value: i32,
+    },
+}
+
+impl fmt::Display for Action {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = |path: &Vec<Side>| {
+            path.iter()
+                .map(|side| match side {
+                    Side::Left => 'L',
+                    Side::Right => 'R',
+                })
+                .collect::<String>()
+        };
+
         match self {
-            Number::Literal(literal) => write!(f, "{}", literal)?,
-            Number::Pair(p1, p2) => {
-                write!(f, "[")?;
-                fmt::Display::fmt(&p1.borrow(), f)?;
-                write!(f, ",")?;
-                fmt::Display::fmt(&p2.borrow(), f)?;
-                write!(f, "]")?;
+            Action::Explode {
+                path: p,
+                left,
+                right,
+            } => {
+                write!(f, "explode [{},{}] at {}", left, right, path(p))
             }
+            Action::Split { path: p, value } => write!(f, "split {} at {}", value, path(p)),
         }
-
-        Ok(())
     }
 }
 
-struct Combinations {
-    values: Vec<usize>,
-    indexes: Vec<usize>,
-    n: usize,
-    first: bool,
+/// Reduction of a number one action at a time, each explode or split as the puzzle describes
+/// them. Much slower than `Number::reduce` but shows what happens.
+struct Reduction {
+    number: Number,
 }
 
-impl Combinations {
-    fn new(values: &Vec<usize>, n: usize) -> Combinations {
-        Combinations {
-            values: values.clone(),
-            indexes: (0..n).collect(),
-            n,
-            first: true,
-        }
+impl Iterator for Reduction {
+    type Item = (Action, Number);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (action, number) = self.number.step()?;
+        self.number = number.clone();
+        Some((action, number))
     }
 }
 
-impl Iterator for Combinations {
-    type Item = Vec<usize>;
+impl Number {
+    fn reduction(&self) -> Reduction {
+        Reduction {
+            number: self.clone(),
+        }
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.first {
-            self.first = false;
-            return Some(
-                self.indexes
-                    .iter()
-                    .map(|i| self.values[*i])
-                    .collect::<Self::Item>(),
+    /// The leftmost explosion, otherwise the leftmost split, with the number after it.
+    fn step(&self) -> Option<(Action, Number)> {
+        let mut leaves = self.leaves.clone();
+
+        if let Some(i) = self.exploding_pair() {
+            let (left, right) = (leaves[i], leaves[i + 1]);
+            let mut path = self.path(i);
+            path.pop();
+
+            if i > 0 {
+                leaves[i - 1].value += left.value;
+            }
+            if let Some(next) = leaves.get_mut(i + 2) {
+                next.value += right.value;
+            }
+            leaves.splice(
+                i..(i + 2),
+                [Leaf {
+                    value: 0,
+                    depth: left.depth - 1,
+                }],
             );
+
+            let action = Action::Explode {
+                path,
+                left: left.value,
+                right: right.value,
+            };
+            return Some((action, Number { leaves }));
         }
 
-        let n_values = self.values.len();
-        let n = self.n;
-        {
-            let v = &mut self.indexes;
-            let l = n - 1;
-            for i in 0..n {
-                if v[l - i] == n_values - 1 - i {
-                    if i == n - 1 {
-                        return None;
-                    }
-                    v[l - i] = v[l - i - 1] + 2;
-                    if i > 0 {
-                        for j in (0..=(i - 1)).rev() {
-                            v[l - j] = v[l - (j + 1)] + 1;
-                        }
-                    }
-                } else {
-                    v[l - i] += 1;
+        let i = leaves.iter().position(|l| l.value >= 10)?;
+        let Leaf { value, depth } = leaves[i];
+        leaves.splice(
+            i..=i,
+            [
+                Leaf {
+                    value: value / 2,
+                    depth: depth + 1,
+                },
+                Leaf {
+                    value: value - value / 2,
+                    depth: depth + 1,
+                },
+            ],
+        );
+
+        let action = Action::Split {
+            path: self.path(i),
+            value,
+        };
+        Some((action, Number { leaves }))
+    }
+
+    /// Index of the left number of the leftmost pair of regular numbers nested too deep. The
+    /// leftmost deep number isn't always in one, e.g. in `[[[[[1,[2,3]],4],5],6],7]`.
+    fn exploding_pair(&self) -> Option<usize> {
+        // Whether each complete part on the stack is a regular number, as in `magnitude`.
+        let mut stack: Vec<(u32, bool)> = vec![];
+        for (i, leaf) in self.leaves.iter().enumerate() {
+            stack.push((leaf.depth, true));
+            while let [.., (left, left_regular), (right, right_regular)] = stack[..] {
+                if left != right {
                     break;
                 }
+                if left_regular && right_regular && left > Self::MAX_DEPTH {
+                    return Some(i - 1);
+                }
+                stack.truncate(stack.len() - 2);
+                stack.push((left - 1, false));
             }
         }
+        None
+    }
 
-        let mut next = Vec::with_capacity(n_values);
-        for i in 0..n {
-            next.push(self.values[self.indexes[i]]);
+    /// Path to the regular number at `idx` from the outermost pair.
+    fn path(&self, idx: usize) -> Vec<Side> {
+        let mut path = vec![];
+        for (i, leaf) in self.leaves.iter().enumerate().take(idx + 1) {
+            // The next number is the right side of the innermost pair whose left side this was.
+            if i > 0 {
+                while path.last() == Some(&Side::Right) {
+                    path.pop();
+                }
+                path.pop();
+                path.push(Side::Right);
+            }
+            while path.len() < leaf.depth as usize {
+                path.push(Side::Left);
+            }
         }
-        Some(next)
+        path
     }
 }
 
-fn part1(input: &Input) -> i32 {
-    Number::add_all(input).magnitude()
+/// Prints every addition of the homework and the reduction steps after it.
+fn print_trace(input: &Input) {
+    let mut added = input[0].clone();
+    println!("{}", added);
+
+    for n in input.iter().skip(1) {
+        added = added.join(n);
+        println!("+ {}", n);
+        println!("  after addition: {}", added);
+        for (action, number) in added.reduction() {
+            println!("  {}: {}", action, number);
+            added = number;
+        }
+    }
 }
 
-fn part2(input: &Input) -> i32 {
-    let mut max_magnitude = 0;
-    let indexes = (0..input.len()).collect::<Vec<_>>();
-    for comb in Combinations::new(&indexes, 2) {
-        for numbers in [&comb, &comb.iter().rev().cloned().collect::<Vec<_>>()]
-            .iter()
-            .map(|idxs| idxs.iter().map(|&i| input[i].clone()).collect::<Vec<_>>())
-        {
-            let magnitude = Number::add_all(&numbers).magnitude();
-            max_magnitude = cmp::max(max_magnitude, magnitude);
+/// Checks the homework is reduced, as `+` expects, and sums it with the checks on.
+fn print_check(input: &Input) -> Result<()> {
+    for (i, n) in input.iter().enumerate() {
+        if let Err(e) = n.check_reduced() {
+            println!("Line {}: {}", i + 1, e);
         }
     }
-    max_magnitude
+    let reduced = input.iter().filter(|n| n.is_reduced()).count();
+    println!("{} of {} numbers are reduced", reduced, input.len());
+
+    let (first, rest) = input.split_first().context("Nothing to sum")?;
+    let sum = rest
+        .iter()
+        .try_fold(first.clone(), |sum, n| sum.checked_add(n))?;
+    println!(
+        "Sum: {} regular numbers, {} pairs deep",
+        sum.leaf_count(),
+        sum.depth()
+    );
+    Ok(())
+}
+
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn write(
+            f: &mut fmt::Formatter<'_>,
+            leaves: &[Leaf],
+            idx: &mut usize,
+            depth: u32,
+        ) -> fmt::Result {
+            let leaf = leaves.get(*idx).ok_or(fmt::Error)?;
+            if leaf.depth <= depth {
+                *idx += 1;
+                return write!(f, "{}", leaf.value);
+            }
+
+            write!(f, "[")?;
+            write(f, leaves, idx, depth + 1)?;
+            write!(f, ",")?;
+            write(f, leaves, idx, depth + 1)?;
+            write!(f, "]")
+        }
+
+        write(f, &self.leaves, &mut 0, 0)
+    }
+}
+
+fn part1(input: &Input) -> BigUint {
+    Number::add_all(input).magnitude()
+}
+
+fn part2(input: &Input) -> BigUint {
+    let indexes = (0..input.len()).collect::<Vec<_>>();
+    combinations(&indexes, 2)
+        .par_bridge()
+        .map(|comb| {
+            let (a, b) = (&input[comb[0]], &input[comb[1]]);
+            cmp::max((a + b).magnitude(), (b + a).magnitude())
+        })
+        .max()
+        .unwrap_or_default()
 }
 
 fn main() -> Result<()> {
     measure(|| {
         let input = input()?;
+        if env::args().any(|arg| arg == "--trace") {
+            print_trace(&input);
+        }
+        if env::args().any(|arg| arg == "--check") {
+            print_check(&input)?;
+        }
         println!("Part1: {}", part1(&input));
         println!("Part2: {}", part2(&input));
         Ok(())
@@ -317,42 +472,76 @@ fn main() -> Result<()> {
 }
 
 impl FromStr for Number {
-    type Err = ParseIntError;
+    type Err = anyhow::Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        fn parse(s: &str) -> Result<(Number, usize), ParseIntError> {
-            if s.chars().next() == Some('[') {
-                let (p1, consumed1) = parse(&s[1..])?;
-                let (p2, consumed2) = parse(&s[(1 + consumed1 + 1)..])?;
-
-                Ok((
-                    Number::Pair(RefCell::new(Box::new(p1)), RefCell::new(Box::new(p2))),
-                    consumed1 + consumed2 + 3,
-                ))
-            } else {
-                let s = s
-                    .chars()
-                    .take_while(|&c| c != '[' && c != ']' && c != ',')
-                    .collect::<String>();
-                Ok((Number::Literal(s.parse::<i32>()?), s.len()))
+        let mut leaves = vec![];
+        // Whether each open pair has had its comma yet.
+        let mut open: Vec<bool> = vec![];
+        let mut expect_number = true;
+        let mut total = 0_i32;
+
+        let mut chars = s.trim().char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            let column = i + 1;
+            match (expect_number, c, open.last_mut()) {
+                (true, '[', _) => open.push(false),
+                (true, '0'..='9', _) => {
+                    let mut digits = c.to_string();
+                    while let Some((_, c)) = chars.next_if(|(_, c)| c.is_ascii_digit()) {
+                        digits.push(c);
+                    }
+                    let value = digits
+                        .parse()
+                        .with_context(|| format!("Bad number at column {}", column))?;
+                    total = total
+                        .checked_add(value)
+                        .filter(|&total| total <= Number::MAX_TOTAL)
+                        .with_context(|| {
+                            format!(
+                                "Regular numbers add up to more than {} at column {}",
+                                Number::MAX_TOTAL,
+                                column
+                            )
+                        })?;
+                    leaves.push(Leaf {
+                        value,
+                        depth: open.len() as u32,
+                    });
+                    expect_number = false;
+                }
+                (false, ',', Some(comma @ false)) => {
+                    *comma = true;
+                    expect_number = true;
+                }
+                (false, ']', Some(true)) => {
+                    open.pop();
+                }
+                (true, _, _) => {
+                    return Err(anyhow!("Expected '[' or a digit at column {}", column))
+                }
+                (false, _, Some(false)) => {
+                    return Err(anyhow!("Expected ',' at column {}", column))
+                }
+                (false, _, Some(true)) => return Err(anyhow!("Expected ']' at column {}", column)),
+                (false, _, None) => return Err(anyhow!("Unexpected '{}' at column {}", c, column)),
             }
         }
 
-        let (n, _) = parse(s)?;
-        Ok(n)
+        if expect_number || !open.is_empty() {
+            return Err(anyhow!("Unexpected end of {:?}", s));
+        }
+        Ok(Number { leaves })
     }
 }
 
 fn read_input<R: Read>(reader: BufReader<R>) -> Result<Input> {
-    reader
-        .lines()
-        .map(|line| Ok(line?.parse::<Number>()?))
-        .collect()
+    reader.lines().map(|line| line?.parse::<Number>()).collect()
 }
 
 fn input() -> Result<Input> {
     let path = env::args()
         .skip(1)
-        .next()
+        .find(|arg| !arg.starts_with("--"))
         .with_context(|| format!("No input file given"))?;
     read_input(BufReader::new(File::open(path)?))
 }
@@ -413,6 +602,163 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_leaves() -> Result<()> {
+        let n = "[[1,[2,3]],[[4,5],6]]".parse::<Number>()?;
+        assert_eq!(
+            n.leaves
+                .iter()
+                .map(|l| (l.value, l.depth))
+                .collect::<Vec<_>>(),
+            vec![(1, 2), (2, 3), (3, 3), (4, 3), (5, 3), (6, 2)]
+        );
+        assert_eq!(format!("{}", n), "[[1,[2,3]],[[4,5],6]]");
+        assert_eq!(format!("{}", "7".parse::<Number>()?), "7");
+        assert_eq!("7".parse::<Number>()?.magnitude(), BigUint::from(7_u32));
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_cascade() -> Result<()> {
+        // Each split explodes right away and pushes the number on its left over 10 again.
+        let n = "[[[[9,20],0],0],0]".parse::<Number>()?.reduce();
+        assert_eq!(format!("{}", n), "[[[[5,0],[7,8]],0],0]");
+        Ok(())
+    }
+
+    #[test]
+    fn test_reduction() -> Result<()> {
+        let a = "[[[[4,3],4],4],[7,[[8,4],9]]]".parse::<Number>()?;
+        let b = "[1,1]".parse::<Number>()?;
+        let trace = a
+            .join(&b)
+            .reduction()
+            .map(|(action, number)| format!("{}: {}", action, number))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            trace,
+            vec![
+                "explode [4,3] at LLLL: [[[[0,7],4],[7,[[8,4],9]]],[1,1]]",
+                "explode [8,4] at LRRL: [[[[0,7],4],[15,[0,13]]],[1,1]]",
+                "split 15 at LRL: [[[[0,7],4],[[7,8],[0,13]]],[1,1]]",
+                "split 13 at LRRR: [[[[0,7],4],[[7,8],[0,[6,7]]]],[1,1]]",
+                "explode [6,7] at LRRR: [[[[0,7],4],[[7,8],[6,0]]],[8,1]]",
+            ]
+        );
+
+        let numbers = as_input(INPUT)?;
+        let mut added = numbers[0].clone();
+        for n in numbers.iter().skip(1) {
+            added = added.join(n);
+            let stepped = added.reduction().last().map(|(_, n)| n);
+            added = added.reduce();
+            assert_eq!(stepped.as_ref(), Some(&added));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for (source, error) in [
+            ("", "Unexpected end"),
+            ("[1,2", "Unexpected end"),
+            ("[1 2]", "Expected ',' at column 3"),
+            ("[1,2,3]", "Expected ']' at column 5"),
+            ("[,2]", "Expected '[' or a digit at column 2"),
+            ("[1,2]]", "Unexpected ']' at column 6"),
+            ("[1,-2]", "Expected '[' or a digit at column 4"),
+            ("[99999999999,1]", "Bad number at column 2"),
+            (
+                "[[[[[1,2000000000],2000000000],2],3],4]",
+                "Regular numbers add up to more than 1073741823 at column 8",
+            ),
+            (
+                "[1073741823,1]",
+                "Regular numbers add up to more than 1073741823 at column 13",
+            ),
+        ] {
+            let message = source.parse::<Number>().unwrap_err().to_string();
+            assert!(message.starts_with(error), "{}: {}", source, message);
+        }
+    }
+
+    #[test]
+    fn test_arithmetic() -> Result<()> {
+        let numbers = as_input(INPUT)?;
+        let sum = numbers.iter().sum::<Number>();
+        assert_eq!(sum, numbers.clone().into_iter().sum());
+        assert_eq!(sum, Number::add_all(&numbers));
+        assert_eq!(
+            numbers[0].clone() + numbers[1].clone(),
+            numbers[0].checked_add(&numbers[1])?
+        );
+
+        assert!(sum.is_reduced());
+        assert_eq!(sum.depth(), 4);
+        assert_eq!(sum.leaf_count(), 16);
+
+        let deep = "[[[[[9,8],1],2],3],4]".parse::<Number>()?;
+        assert!(!deep.is_reduced());
+        assert_eq!(
+            deep.check_reduced().unwrap_err().to_string(),
+            "Number isn't reduced: explode [9,8] at LLLL"
+        );
+        assert!(deep.checked_add(&sum).is_err());
+        assert!(sum.checked_add(&"[10,1]".parse()?).is_err());
+
+        // The largest regular numbers there can be still explode and add up without overflowing.
+        let big = "[[[[[1,1073741822],0],0],0],0]".parse::<Number>()?;
+        let (_, exploded) = big.step().context("No step")?;
+        assert_eq!(format!("{}", exploded), "[[[[0,1073741822],0],0],0]");
+        let joined = big.join(&"[1073741823,0]".parse()?);
+        let (_, exploded) = joined.step().context("No step")?;
+        assert_eq!(
+            exploded.leaves.iter().map(|l| l.value).sum::<i32>(),
+            i32::MAX - 2
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_adding_unreduced() -> Result<()> {
+        let deep = "[[[[[9,8],1],2],3],4]".parse::<Number>()?;
+        let sum = &deep + &"[1,1]".parse()?;
+        assert!(sum.is_reduced());
+        assert_eq!(format!("{}", sum), "[[[[5,0],9],4],[1,1]]");
+
+        // The leftmost deep number isn't in the leftmost exploding pair.
+        let n = "[[[[[1,[2,3]],4],5],6],7]".parse::<Number>()?;
+        let (action, _) = n.step().context("No step")?;
+        assert_eq!(format!("{}", action), "explode [2,3] at LLLLR");
+        assert_eq!(format!("{}", n.reduce()), "[[[[0,7],5],6],7]");
+
+        let numbers = [deep.clone(), n, "[[1,[2,[3,[4,[5,[6,7]]]]]],30]".parse()?];
+        let sum = numbers.iter().sum::<Number>();
+        assert!(sum.is_reduced());
+        assert_eq!(
+            sum,
+            numbers[1..].iter().fold(deep, |sum, n| {
+                let (_, reduced) = sum.join(n).reduction().last().unwrap();
+                reduced
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_deep_magnitude() -> Result<()> {
+        // Each pair around the innermost [9,9] triples its magnitude of 45.
+        let depth = 100;
+        let source = format!("{}[9,9]{}", "[".repeat(depth - 1), ",0]".repeat(depth - 1));
+        let n = source.parse::<Number>()?;
+        assert_eq!(n.depth(), 100);
+        assert_eq!(
+            n.magnitude(),
+            BigUint::from(45_u32) * BigUint::from(3_u32).pow(99)
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_adding() -> Result<()> {
         let numbers = as_input(
@@ -472,47 +818,56 @@ mod tests {
 
     #[test]
     fn test_magnitude() -> Result<()> {
-        assert_eq!("[9,1]".parse::<Number>().unwrap().magnitude(), 29);
-        assert_eq!("[1,9]".parse::<Number>().unwrap().magnitude(), 21);
-        assert_eq!("[[9,1],[1,9]]".parse::<Number>().unwrap().magnitude(), 129);
+        assert_eq!(
+            "[9,1]".parse::<Number>().unwrap().magnitude(),
+            BigUint::from(29_u32)
+        );
+        assert_eq!(
+            "[1,9]".parse::<Number>().unwrap().magnitude(),
+            BigUint::from(21_u32)
+        );
+        assert_eq!(
+            "[[9,1],[1,9]]".parse::<Number>().unwrap().magnitude(),
+            BigUint::from(129_u32)
+        );
         assert_eq!(
             "[[1,2],[[3,4],5]]".parse::<Number>().unwrap().magnitude(),
-            143
+            BigUint::from(143_u32)
         );
         assert_eq!(
             "[[[[0,7],4],[[7,8],[6,0]]],[8,1]]"
                 .parse::<Number>()
                 .unwrap()
                 .magnitude(),
-            1384
+            BigUint::from(1384_u32)
         );
         assert_eq!(
             "[[[[1,1],[2,2]],[3,3]],[4,4]]"
                 .parse::<Number>()
                 .unwrap()
                 .magnitude(),
-            445
+            BigUint::from(445_u32)
         );
         assert_eq!(
             "[[[[3,0],[5,3]],[4,4]],[5,5]]"
                 .parse::<Number>()
                 .unwrap()
                 .magnitude(),
-            791
+            BigUint::from(791_u32)
         );
         assert_eq!(
             "[[[[5,0],[7,4]],[5,5]],[6,6]]"
                 .parse::<Number>()
                 .unwrap()
                 .magnitude(),
-            1137
+            BigUint::from(1137_u32)
         );
         assert_eq!(
             "[[[[8,7],[7,7]],[[8,6],[7,7]]],[[[0,7],[6,6]],[8,7]]]"
                 .parse::<Number>()
                 .unwrap()
                 .magnitude(),
-            3488
+            BigUint::from(3488_u32)
         );
 
         Ok(())
@@ -520,13 +875,13 @@ mod tests {
 
     #[test]
     fn test_part1() -> Result<()> {
-        assert_eq!(part1(&as_input(INPUT)?), 4140);
+        assert_eq!(part1(&as_input(INPUT)?), BigUint::from(4140_u32));
         Ok(())
     }
 
     #[test]
     fn test_part2() -> Result<()> {
-        assert_eq!(part2(&as_input(INPUT)?), 3993);
+        assert_eq!(part2(&as_input(INPUT)?), BigUint::from(3993_u32));
         Ok(())
     }
 }
@@ -10,6 +10,7 @@ use std::str::FromStr;
 
 use anyhow::{Context, Result};
 
+use utils::combinatorics::{combinations, power};
 use utils::measure;
 
 type Input = Vec<Scanner>;
@@ -134,19 +135,11 @@ impl fmt::Display for Vec3 {
 
 /// The 24 possble 90 degree per axis rotations
 fn rotations() -> Vec<(i32, i32, i32)> {
-    let mut rots = BTreeSet::new();
-    for x in 0..=3 {
-        for y in 0..=3 {
-            for z in 0..=3 {
-                rots.insert((x, y, z));
-            }
-        }
-    }
-
     let pos = Vec3::new(1, 2, 3);
     let mut rot_results = HashMap::<Vec3, BTreeSet<(i32, i32, i32)>>::new();
 
-    for (x, y, z) in rots {
+    for rot in power(&[0, 1, 2, 3], 3) {
+        let (x, y, z) = (rot[0], rot[1], rot[2]);
         let mut p = pos.clone();
 
         for _ in 0..x {
@@ -272,18 +265,11 @@ fn solve(scanners: &Input) -> (usize, i32) {
 
     let beacons = beacons.into_iter().collect::<Vec<_>>();
 
-    let mut max_manh_dist = 0;
-
-    for b1 in 0..scanners.len() {
-        for b2 in 0..scanners.len() {
-            let p1 = positions[&b1].0;
-            let p2 = positions[&b2].0;
-            let dist = p1.manh_dist(&p2);
-            if dist > max_manh_dist {
-                max_manh_dist = dist;
-            }
-        }
-    }
+    let scanner_positions = positions.values().map(|(p, _)| *p).collect::<Vec<_>>();
+    let max_manh_dist = combinations(&scanner_positions, 2)
+        .map(|pair| pair[0].manh_dist(&pair[1]))
+        .max()
+        .unwrap_or(0);
 
     (beacons.len(), max_manh_dist)
 }
@@ -1,4 +1,4 @@
-use std::collections::{BTreeMap, VecDeque};
+use std::collections::VecDeque;
 use std::env;
 use std::fs::File;
 use std::io::prelude::*;
@@ -6,6 +6,7 @@ use std::io::BufReader;
 
 use anyhow::{Context, Result};
 
+use utils::combinatorics::dice_sums;
 use utils::measure;
 
 type Input = [usize; 2];
@@ -49,15 +50,7 @@ fn part2(input: &Input) -> usize {
     queue.push_back((player_pos, player_score, player_turn, 1));
 
     let mut wins = [0_usize, 0];
-    let mut roll_freqs = BTreeMap::<usize, usize>::new();
-    for roll1 in 1..=3 {
-        for roll2 in 1..=3 {
-            for roll3 in 1..=3 {
-                let roll = roll1 + roll2 + roll3;
-                *roll_freqs.entry(roll).or_default() += 1;
-            }
-        }
-    }
+    let roll_freqs = dice_sums(&[1, 2, 3], 3);
 
     while let Some((player_pos, player_score, player_turn, factor)) = queue.pop_front() {
         let next_player_turn = (player_turn + 1) % 2;
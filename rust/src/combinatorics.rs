@@ -0,0 +1,220 @@
+use std::collections::BTreeMap;
+
+/// Combinations of `k` items, in the order of their positions in `items`.
+#[derive(Debug, Clone)]
+pub struct Combinations<'a, T> {
+    items: &'a [T],
+    indexes: Vec<usize>,
+    done: bool,
+}
+
+pub fn combinations<T: Clone>(items: &[T], k: usize) -> Combinations<'_, T> {
+    Combinations {
+        items,
+        indexes: (0..k).collect(),
+        done: k > items.len(),
+    }
+}
+
+impl<'a, T: Clone> Iterator for Combinations<'a, T> {
+    type Item = Vec<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let next = self
+            .indexes
+            .iter()
+            .map(|&i| self.items[i].clone())
+            .collect();
+
+        // Move the last index that can still move right, and put the ones after it right behind.
+        let (n, k) = (self.items.len(), self.indexes.len());
+        match (0..k).rev().find(|&i| self.indexes[i] < n - k + i) {
+            Some(i) => {
+                self.indexes[i] += 1;
+                for j in (i + 1)..k {
+                    self.indexes[j] = self.indexes[j - 1] + 1;
+                }
+            }
+            None => self.done = true,
+        }
+        Some(next)
+    }
+}
+
+/// Ordered arrangements of `k` items, in the order of their positions in `items`.
+#[derive(Debug, Clone)]
+pub struct Permutations<'a, T> {
+    items: &'a [T],
+    k: usize,
+    indexes: Vec<usize>,
+    done: bool,
+}
+
+pub fn permutations<T: Clone>(items: &[T], k: usize) -> Permutations<'_, T> {
+    Permutations {
+        items,
+        k,
+        indexes: (0..items.len()).collect(),
+        done: k > items.len(),
+    }
+}
+
+impl<'a, T: Clone> Iterator for Permutations<'a, T> {
+    type Item = Vec<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let next = self.indexes[..self.k]
+            .iter()
+            .map(|&i| self.items[i].clone())
+            .collect();
+
+        // With the unused indexes reversed, the next full permutation starts with the next
+        // arrangement of `k` and leaves the unused ones sorted again.
+        let v = &mut self.indexes;
+        v[self.k..].reverse();
+        match (1..v.len()).rev().find(|&i| v[i - 1] < v[i]) {
+            Some(i) => {
+                let j = (i..v.len()).rev().find(|&j| v[j] > v[i - 1]).unwrap_or(i);
+                v.swap(i - 1, j);
+                v[i..].reverse();
+            }
+            None => self.done = true,
+        }
+        Some(next)
+    }
+}
+
+/// Every way of picking one item from each of `sets`, the last set changing fastest.
+#[derive(Debug, Clone)]
+pub struct Product<T> {
+    sets: Vec<Vec<T>>,
+    indexes: Vec<usize>,
+    done: bool,
+}
+
+pub fn product<T: Clone>(sets: Vec<Vec<T>>) -> Product<T> {
+    Product {
+        done: sets.iter().any(|set| set.is_empty()),
+        indexes: vec![0; sets.len()],
+        sets,
+    }
+}
+
+/// Every sequence of `k` items from `items`, like `k` nested loops over them.
+pub fn power<T: Clone>(items: &[T], k: usize) -> Product<T> {
+    product(vec![items.to_vec(); k])
+}
+
+impl<T: Clone> Iterator for Product<T> {
+    type Item = Vec<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let next = self
+            .indexes
+            .iter()
+            .zip(&self.sets)
+            .map(|(&i, set)| set[i].clone())
+            .collect();
+
+        self.done = true;
+        for (i, set) in self.indexes.iter_mut().zip(&self.sets).rev() {
+            *i += 1;
+            if *i < set.len() {
+                self.done = false;
+                break;
+            }
+            *i = 0;
+        }
+        Some(next)
+    }
+}
+
+/// In how many ways each total can be rolled with `dice` dice showing `faces`, worked out one
+/// die at a time.
+pub fn dice_sums(faces: &[usize], dice: usize) -> BTreeMap<usize, usize> {
+    let mut sums = BTreeMap::from([(0, 1)]);
+    for _ in 0..dice {
+        let mut next = BTreeMap::new();
+        for (sum, ways) in sums {
+            for face in faces {
+                *next.entry(sum + face).or_default() += ways;
+            }
+        }
+        sums = next;
+    }
+    sums
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_combinations() {
+        assert_eq!(
+            combinations(&[1, 2, 3, 4], 2).collect::<Vec<_>>(),
+            vec![
+                vec![1, 2],
+                vec![1, 3],
+                vec![1, 4],
+                vec![2, 3],
+                vec![2, 4],
+                vec![3, 4]
+            ]
+        );
+        assert_eq!(combinations(&[1, 2, 3, 4, 5, 6], 3).count(), 20);
+        assert_eq!(combinations(&[1, 2], 0).collect::<Vec<_>>(), vec![vec![]]);
+        assert_eq!(combinations(&[1, 2], 3).count(), 0);
+    }
+
+    #[test]
+    fn test_permutations() {
+        assert_eq!(
+            permutations(&['a', 'b', 'c'], 2).collect::<Vec<_>>(),
+            vec![
+                vec!['a', 'b'],
+                vec!['a', 'c'],
+                vec!['b', 'a'],
+                vec!['b', 'c'],
+                vec!['c', 'a'],
+                vec!['c', 'b']
+            ]
+        );
+        assert_eq!(permutations(&[1, 2, 3, 4, 5], 5).count(), 120);
+        assert_eq!(permutations(&[1, 2, 3, 4, 5], 3).count(), 60);
+        assert_eq!(permutations(&[1, 2], 0).collect::<Vec<_>>(), vec![vec![]]);
+        assert_eq!(permutations(&[1, 2], 3).count(), 0);
+    }
+
+    #[test]
+    fn test_product() {
+        assert_eq!(
+            product(vec![vec![1, 2], vec![3], vec![4, 5]]).collect::<Vec<_>>(),
+            vec![vec![1, 3, 4], vec![1, 3, 5], vec![2, 3, 4], vec![2, 3, 5]]
+        );
+        assert_eq!(power(&[0, 1, 2, 3], 3).count(), 64);
+        assert_eq!(power(&[0, 1], 0).collect::<Vec<_>>(), vec![vec![]]);
+        assert_eq!(product(vec![vec![1], vec![]]).count(), 0);
+    }
+
+    #[test]
+    fn test_dice_sums() {
+        assert_eq!(
+            dice_sums(&[1, 2, 3], 3).into_iter().collect::<Vec<_>>(),
+            vec![(3, 1), (4, 3), (5, 6), (6, 7), (7, 6), (8, 3), (9, 1)]
+        );
+        let d6 = dice_sums(&[1, 2, 3, 4, 5, 6], 2);
+        assert_eq!(d6[&7], 6);
+        assert_eq!(d6.values().sum::<usize>(), 36);
+        assert_eq!(dice_sums(&[1, 2], 0), BTreeMap::from([(0, 1)]));
+    }
+}
@@ -1,7 +1,13 @@
 extern crate time;
 
+use std::env;
+
 use time::*;
 
+pub mod combinatorics;
+pub mod ocr;
+pub mod union_find;
+
 pub fn measure<F, S, T>(f: F) -> Result<S, T>
 where
     F: Fn() -> Result<S, T>,
@@ -44,3 +50,8 @@ where
     }
     Ok(res)
 }
+
+/// The value following `name` on the command line.
+pub fn flag(name: &str) -> Option<String> {
+    env::args().skip_while(|arg| arg != name).nth(1)
+}
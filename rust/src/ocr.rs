@@ -0,0 +1,75 @@
+use anyhow::{anyhow, Result};
+
+pub const GLYPH_WIDTH: usize = 4;
+pub const GLYPH_HEIGHT: usize = 6;
+
+/// The 4x6 dot-matrix font used by puzzles, rows from top to bottom with `#` for a lit dot.
+const GLYPHS: [(char, [&str; GLYPH_HEIGHT]); 17] = [
+    ('A', [".##.", "#..#", "#..#", "####", "#..#", "#..#"]),
+    ('B', ["###.", "#..#", "###.", "#..#", "#..#", "###."]),
+    ('C', [".##.", "#..#", "#...", "#...", "#..#", ".##."]),
+    ('E', ["####", "#...", "###.", "#...", "#...", "####"]),
+    ('F', ["####", "#...", "###.", "#...", "#...", "#..."]),
+    ('G', [".##.", "#..#", "#...", "#.##", "#..#", ".###"]),
+    ('H', ["#..#", "#..#", "####", "#..#", "#..#", "#..#"]),
+    ('I', [".###", "..#.", "..#.", "..#.", "..#.", ".###"]),
+    ('J', ["..##", "...#", "...#", "...#", "#..#", ".##."]),
+    ('K', ["#..#", "#.#.", "##..", "#.#.", "#.#.", "#..#"]),
+    ('L', ["#...", "#...", "#...", "#...", "#...", "####"]),
+    ('O', [".##.", "#..#", "#..#", "#..#", "#..#", ".##."]),
+    ('P', ["###.", "#..#", "#..#", "###.", "#...", "#..."]),
+    ('R', ["###.", "#..#", "#..#", "###.", "#.#.", "#..#"]),
+    ('S', [".###", "#...", "#...", ".##.", "...#", "###."]),
+    ('U', ["#..#", "#..#", "#..#", "#..#", "#..#", ".##."]),
+    ('Z', ["####", "...#", "..#.", ".#..", "#...", "####"]),
+];
+
+/// Reads the letters of a dot-matrix display `width` dots wide, where `lit(x, y)` tells if the
+/// dot at x, y is lit. Letters are expected to start at x = 0 with one blank column between them.
+pub fn read_letters<F>(width: usize, lit: F) -> Result<String>
+where
+    F: Fn(usize, usize) -> bool,
+{
+    (0..(width + 1) / (GLYPH_WIDTH + 1))
+        .map(|i| {
+            let x0 = i * (GLYPH_WIDTH + 1);
+            GLYPHS
+                .iter()
+                .find(|(_, rows)| {
+                    rows.iter().enumerate().all(|(y, row)| {
+                        row.bytes()
+                            .enumerate()
+                            .all(|(x, b)| (b == b'#') == lit(x0 + x, y))
+                    })
+                })
+                .map(|&(c, _)| c)
+                .ok_or_else(|| anyhow!("Unrecognized letter at x = {}", x0))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(rows: &[&str]) -> Result<String> {
+        read_letters(rows[0].len(), |x, y| rows[y].as_bytes()[x] == b'#')
+    }
+
+    #[test]
+    fn test_read_letters() -> Result<()> {
+        let rows = [
+            "####..##..#..#.#..#.###..####..##..###.",
+            "#....#..#.#..#.#.#..#..#.#....#..#.#..#",
+            "###..#..#.####.##...#..#.###..#....#..#",
+            "#....####.#..#.#.#..###..#....#....###.",
+            "#....#..#.#..#.#.#..#.#..#....#..#.#...",
+            "####.#..#.#..#.#..#.#..#.####..##..#...",
+        ];
+        assert_eq!(read(&rows)?, "EAHKRECP");
+
+        let rows = ["#...", "#...", "#...", "#...", "#...", "###."];
+        assert!(read(&rows).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,145 @@
+/// Disjoint-set forest over the elements `0..n`, using path compression and union by rank.
+#[derive(Debug, Clone)]
+pub struct UnionFind {
+    parent: Vec<usize>,
+    rank: Vec<u8>,
+}
+
+impl UnionFind {
+    pub fn new(n: usize) -> UnionFind {
+        UnionFind {
+            parent: (0..n).collect(),
+            rank: vec![0; n],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.parent.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.parent.is_empty()
+    }
+
+    /// Returns the representative of the set containing `x`.
+    pub fn find(&mut self, x: usize) -> usize {
+        let mut root = x;
+        while self.parent[root] != root {
+            root = self.parent[root];
+        }
+
+        let mut x = x;
+        while self.parent[x] != root {
+            let next = self.parent[x];
+            self.parent[x] = root;
+            x = next;
+        }
+
+        root
+    }
+
+    /// Merges the sets containing `a` and `b`, returns false if they already were the same set.
+    pub fn union(&mut self, a: usize, b: usize) -> bool {
+        let a = self.find(a);
+        let b = self.find(b);
+        if a == b {
+            return false;
+        }
+
+        if self.rank[a] < self.rank[b] {
+            self.parent[a] = b;
+        } else {
+            self.parent[b] = a;
+            if self.rank[a] == self.rank[b] {
+                self.rank[a] += 1;
+            }
+        }
+        true
+    }
+
+    pub fn connected(&mut self, a: usize, b: usize) -> bool {
+        self.find(a) == self.find(b)
+    }
+}
+
+/// Labels the 4-connected components of the cells in a `width` x `height` grid for which
+/// `include(x, y)` holds, indexed as `labels[y][x]`. Components are numbered from 0 in the
+/// reading order of their first cell, cells not included are `None`.
+pub fn label_components<F>(width: usize, height: usize, include: F) -> Vec<Vec<Option<usize>>>
+where
+    F: Fn(usize, usize) -> bool,
+{
+    let mut sets = UnionFind::new(width * height);
+
+    for y in 0..height {
+        for x in 0..width {
+            if !include(x, y) {
+                continue;
+            }
+            if x > 0 && include(x - 1, y) {
+                sets.union(y * width + x, y * width + x - 1);
+            }
+            if y > 0 && include(x, y - 1) {
+                sets.union(y * width + x, (y - 1) * width + x);
+            }
+        }
+    }
+
+    let mut ids = vec![None; width * height];
+    let mut next_id = 0;
+    let mut labels = vec![vec![None; width]; height];
+
+    for (y, row) in labels.iter_mut().enumerate() {
+        for (x, label) in row.iter_mut().enumerate() {
+            if !include(x, y) {
+                continue;
+            }
+            let root = sets.find(y * width + x);
+            let id = *ids[root].get_or_insert_with(|| {
+                next_id += 1;
+                next_id - 1
+            });
+            *label = Some(id);
+        }
+    }
+
+    labels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_union_find() {
+        let mut sets = UnionFind::new(5);
+        assert!(sets.union(0, 1));
+        assert!(sets.union(3, 4));
+        assert!(!sets.union(1, 0));
+        assert!(sets.connected(0, 1));
+        assert!(!sets.connected(1, 3));
+        assert!(sets.union(1, 4));
+        assert!(sets.connected(0, 3));
+        assert!(!sets.connected(2, 0));
+        assert_eq!(sets.len(), 5);
+    }
+
+    #[test]
+    fn test_label_components() {
+        let grid = ["#.#", "#.#", "..#"];
+        let labels = label_components(3, 3, |x, y| grid[y].as_bytes()[x] == b'#');
+        assert_eq!(
+            labels,
+            vec![
+                vec![Some(0), None, Some(1)],
+                vec![Some(0), None, Some(1)],
+                vec![None, None, Some(1)],
+            ]
+        );
+
+        // A U shape only joins up at the bottom row
+        let grid = ["#.#", "#.#", "###"];
+        let labels = label_components(3, 3, |x, y| grid[y].as_bytes()[x] == b'#');
+        assert_eq!(labels[0][2], Some(0));
+    }
+}